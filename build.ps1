@@ -3,31 +3,41 @@ $errorActionPreference = "Stop"
 $release = $false
 $run = $false
 $useqemu = $false
+$sse = $false
+$smp = $false
+$lockdep = $false
 
 $args | ForEach-Object { 
     if ($_ -eq "release") { $release = $true }  
     if ($_ -eq "run") { $run = $true }  
     if ($_ -eq "qemu") { $useqemu = $true }  
+    if ($_ -eq "sse") { $sse = $true }
+    if ($_ -eq "smp") { $smp = $true }
+    if ($_ -eq "lockdep") { $lockdep = $true }
 }
 
+# The sse target lets the compiler use SSE registers, saved per thread by the kernel.
+if ($sse) { $target = "i686-unknown-none-sse" } else { $target = "i686-unknown-none" }
+if ($lockdep) { $features = @("--features", "lockdep") } else { $features = @() }
+
 if ($release) {
-    if (Test-Path kernel/target/i686-unknown-none/release/kernel) {
-        Remove-Item kernel/target/i686-unknown-none/release/kernel
+    if (Test-Path kernel/target/$target/release/kernel) {
+        Remove-Item kernel/target/$target/release/kernel
     }
 }
 else {
-    if (Test-Path kernel/target/i686-unknown-none/debug/kernel) {
-        Remove-Item kernel/target/i686-unknown-none/debug/kernel
+    if (Test-Path kernel/target/$target/debug/kernel) {
+        Remove-Item kernel/target/$target/debug/kernel
     }
 }
 
 Set-Location kernel
 try {
     if ($release) {
-        cargo build --release 
+        cargo build --release --target "$target.json" @features
     }
     else {
-        cargo build
+        cargo build --target "$target.json" @features
     }
 }
 finally {
@@ -38,10 +48,10 @@ if (Test-Path kernel.bin) {
     Write-Host "Removed Old Kernel"
 }
 if ($release) {
-    Copy-Item kernel/target/i686-unknown-none/release/kernel kernel.bin
+    Copy-Item kernel/target/$target/release/kernel kernel.bin
 }
 else {
-    Copy-Item kernel/target/i686-unknown-none/debug/kernel kernel.bin
+    Copy-Item kernel/target/$target/debug/kernel kernel.bin
 }
 Write-Host([string]::Format("Kernel Size: {0}(0x{0:x}) Bytes", (Get-Item .\kernel.bin).Length))
 nasm -o loader.bin loader.s -l loader.lst
@@ -52,7 +62,9 @@ nasm -o disk.img disk.s -l disk.lst
 Write-Host "Built Disk"
 if ($run) {
     if ($useqemu) {
-        qemu-system-x86_64.exe -drive format=raw,media=disk,file=disk.img -serial stdio
+        # The application processors are started from the ACPI MADT.
+        if ($smp) { $cpus = 4 } else { $cpus = 1 }
+        qemu-system-x86_64.exe -drive format=raw,media=disk,file=disk.img -serial stdio -smp $cpus
     } else {
         ./bochsrc.bxrc
     }
@@ -0,0 +1,36 @@
+#![allow(dead_code)]
+
+use alloc::vec::Vec;
+
+use super::find_table;
+
+/// Multiple APIC description table, lists the interrupt controllers.
+
+/// Local APIC address and flags precede the entries.
+const MADT_ENTRIES_OFFSET: usize = 8;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+
+/// Local APIC entry flags: the processor is usable.
+const LOCAL_APIC_ENABLED: u32 = 1;
+
+/// The APIC ids of the usable processors, `None` if there is no MADT.
+pub fn local_apic_ids() -> Option<Vec<u8>> {
+    let table = find_table(b"APIC")?;
+    let mut entries = table.data().get(MADT_ENTRIES_OFFSET..)?;
+    let mut ids = Vec::new();
+    while let [kind, len, ..] = *entries {
+        let len = len as usize;
+        if len < 2 || len > entries.len() {
+            break;
+        }
+        // Processor id, APIC id, flags.
+        if let (ENTRY_LOCAL_APIC, [_, _, _, apic_id, f0, f1, f2, f3]) = (kind, &entries[..len]) {
+            if u32::from_le_bytes([*f0, *f1, *f2, *f3]) & LOCAL_APIC_ENABLED != 0 {
+                ids.push(*apic_id);
+            }
+        }
+        entries = &entries[len..];
+    }
+    Some(ids)
+}
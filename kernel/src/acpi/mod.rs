@@ -0,0 +1,172 @@
+#![allow(dead_code)]
+
+use core::{mem::size_of, slice};
+
+use crate::{
+    loader::KERNEL_DIRECT_MAP_SIZE,
+    mm::{self, ptov},
+    utils::singleton::Singleton,
+};
+
+pub mod madt;
+
+/// ACPI table discovery.
+
+/// Root system description pointer, found in the BIOS memory.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // ACPI 2.0+
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Size of the ACPI 1.0 part of the RSDP, covered by `checksum`.
+const RSDP_V1_SIZE: usize = 20;
+
+/// Header common to all the system description tables.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// The whole table, header included.
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self as *const _ as *const u8, self.length as usize) }
+    }
+
+    /// The table contents following the header.
+    pub fn data(&self) -> &[u8] {
+        &self.bytes()[size_of::<SdtHeader>()..]
+    }
+}
+
+/// Generic address structure, locates a register block.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Kernel virtual address of SIZE bytes of firmware memory at physical address PADDR.
+fn map_phys(paddr: usize, size: usize) -> usize {
+    if paddr + size <= KERNEL_DIRECT_MAP_SIZE as usize {
+        ptov(paddr)
+    } else {
+        mm::map_mmio(paddr, size)
+    }
+}
+
+fn find_rsdp_in(paddr: usize, len: usize) -> Option<Rsdp> {
+    (paddr..paddr + len)
+        .step_by(16)
+        .map(|paddr| unsafe { (ptov(paddr) as *const Rsdp).read_unaligned() })
+        .find(|rsdp| {
+            rsdp.signature == *b"RSD PTR "
+                && checksum_ok(unsafe {
+                    slice::from_raw_parts(rsdp as *const _ as *const u8, RSDP_V1_SIZE)
+                })
+        })
+}
+
+/// Looks for the RSDP in the first KiB of the EBDA, then in the BIOS ROM area.
+fn find_rsdp() -> Option<Rsdp> {
+    let ebda = unsafe { (ptov(0x40e) as *const u16).read_unaligned() } as usize * 16;
+    (ebda != 0)
+        .then(|| find_rsdp_in(ebda, 1024))
+        .flatten()
+        .or_else(|| find_rsdp_in(0xe0000, 0x20000))
+}
+
+fn map_table(paddr: usize) -> Option<&'static SdtHeader> {
+    let header = unsafe { &*(map_phys(paddr, size_of::<SdtHeader>()) as *const SdtHeader) };
+    let table = unsafe { &*(map_phys(paddr, header.length as usize) as *const SdtHeader) };
+    checksum_ok(table.bytes()).then_some(table)
+}
+
+/// The tables listed by the RSDT (or XSDT).
+pub struct AcpiTables {
+    tables: [Option<&'static SdtHeader>; MAX_TABLES],
+}
+
+const MAX_TABLES: usize = 32;
+
+impl Default for AcpiTables {
+    fn default() -> Self {
+        let mut tables = [None; MAX_TABLES];
+        let Some(rsdp) = find_rsdp() else {
+            return Self { tables };
+        };
+        // The XSDT holds 64-bit pointers, only usable if the tables are below 4 GiB.
+        let (root, entry_size) = match rsdp.revision {
+            2.. if rsdp.xsdt_address != 0 && rsdp.xsdt_address < 1 << 32 => {
+                (map_table(rsdp.xsdt_address as usize), 8)
+            }
+            _ => (map_table(rsdp.rsdt_address as usize), 4),
+        };
+        if let Some(root) = root {
+            for (slot, entry) in tables.iter_mut().zip(root.data().chunks_exact(entry_size)) {
+                let paddr = match *entry {
+                    [a, b, c, d] => u32::from_le_bytes([a, b, c, d]) as u64,
+                    [a, b, c, d, e, f, g, h] => u64::from_le_bytes([a, b, c, d, e, f, g, h]),
+                    _ => unreachable!(),
+                };
+                if paddr < 1 << 32 {
+                    *slot = map_table(paddr as usize);
+                }
+            }
+        }
+        Self { tables }
+    }
+}
+
+static ACPI_TABLES: Singleton<AcpiTables> = Singleton::UNINIT;
+
+/// Offset in the FADT of the CMOS index of the RTC century register.
+const FADT_CENTURY: usize = 108;
+
+/// Finds the table with SIGNATURE, e.g. `b"HPET"`.
+///
+/// The tables are located the first time this is called, it must be at boot since
+/// mapping them may extend the kernel page directory.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    ACPI_TABLES
+        .tables
+        .iter()
+        .flatten()
+        .find(|table| table.signature == *signature)
+        .copied()
+}
+
+/// CMOS index of the RTC century register given by the FADT, `None` if there is none.
+pub fn rtc_century_register() -> Option<u8> {
+    let fadt = find_table(b"FACP")?;
+    fadt.bytes().get(FADT_CENTURY).copied().filter(|&reg| reg != 0)
+}
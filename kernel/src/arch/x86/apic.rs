@@ -0,0 +1,153 @@
+#![allow(dead_code)]
+
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{mm, utils::BitAccess};
+
+use super::cpuid;
+
+/// Local APIC.
+
+/// Physical address of the local APIC registers after reset.
+pub const LAPIC_DEFAULT_BASE: usize = 0xfee0_0000;
+
+const LAPIC_ID: usize = 0x20;
+const LAPIC_VERSION: usize = 0x30;
+const LAPIC_TPR: usize = 0x80;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SVR: usize = 0xf0;
+const LAPIC_ESR: usize = 0x280;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_LINT1: usize = 0x360;
+const LAPIC_LVT_ERROR: usize = 0x370;
+const LAPIC_TIMER_INITIAL: usize = 0x380;
+const LAPIC_TIMER_CURRENT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+/// Spurious interrupt vector register: APIC software enable.
+const SVR_ENABLE: usize = 8;
+/// LVT entries: interrupt masked.
+const LVT_MASKED: usize = 16;
+/// LVT timer mode, bits 17...18.
+const LVT_TIMER_ONESHOT: u32 = 0 << 17;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide configuration: the timer counts at the bus clock divided by 16.
+const TIMER_DIVIDE_16: u32 = 0x3;
+
+/// Interrupt command register: delivery modes, bits 8...10.
+pub const ICR_FIXED: u32 = 0 << 8;
+pub const ICR_INIT: u32 = 5 << 8;
+pub const ICR_STARTUP: u32 = 6 << 8;
+/// The previous IPI is still being sent.
+const ICR_DELIVERY_PENDING: usize = 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_TRIGGER_LEVEL: u32 = 1 << 15;
+
+/// Vector of the local APIC timer, above the PIC vectors.
+pub const LAPIC_TIMER_VECTOR: u8 = 0x30;
+/// Vector the APIC delivers spuriously, it must not be acknowledged.
+pub const LAPIC_SPURIOUS_VECTOR: u8 = 0xff;
+
+/// CPUID leaf 1 EDX: on-chip APIC.
+const CPUID_1_EDX_APIC: usize = 9;
+
+/// Virtual address of the local APIC registers, 0 until mapped.
+static LAPIC_BASE: AtomicUsize = AtomicUsize::new(0);
+
+fn lapic_read(reg: usize) -> u32 {
+    unsafe { read_volatile((LAPIC_BASE.load(Ordering::Relaxed) + reg) as *const u32) }
+}
+
+fn lapic_write(reg: usize, val: u32) {
+    unsafe { write_volatile((LAPIC_BASE.load(Ordering::Relaxed) + reg) as *mut u32, val) }
+}
+
+pub fn has_lapic() -> bool {
+    cpuid(1, 0).edx.get_bit(CPUID_1_EDX_APIC)
+}
+
+pub fn lapic_enabled() -> bool {
+    LAPIC_BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Maps and software-enables the local APIC of the running CPU.
+///
+/// The PIC keeps delivering its interrupts through LINT0, as set up by the BIOS in
+/// virtual wire mode. Returns `false` if the CPU has no local APIC.
+pub fn lapic_init() -> bool {
+    if !has_lapic() {
+        return false;
+    }
+    if !lapic_enabled() {
+        LAPIC_BASE.store(mm::map_mmio(LAPIC_DEFAULT_BASE, 0x1000), Ordering::Relaxed);
+    }
+    lapic_write(LAPIC_TPR, 0);
+    lapic_write(LAPIC_LVT_TIMER, 1 << LVT_MASKED);
+    lapic_write(LAPIC_SVR, 1 << SVR_ENABLE | LAPIC_SPURIOUS_VECTOR as u32);
+    true
+}
+
+/// Sends an inter-processor interrupt to the local APIC APIC_ID, COMMAND being the
+/// delivery mode and vector. Waits until the IPI is sent.
+pub fn lapic_send_ipi(apic_id: u8, command: u32) {
+    lapic_write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
+    lapic_write(LAPIC_ICR_LOW, command | ICR_LEVEL_ASSERT);
+    while lapic_read(LAPIC_ICR_LOW).get_bit(ICR_DELIVERY_PENDING) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Asserts then deasserts INIT on the CPU of APIC_ID, which resets it to wait for a
+/// startup IPI.
+pub fn lapic_send_init(apic_id: u8) {
+    lapic_send_ipi(apic_id, ICR_INIT | ICR_TRIGGER_LEVEL);
+    lapic_write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
+    lapic_write(LAPIC_ICR_LOW, ICR_INIT | ICR_TRIGGER_LEVEL);
+    while lapic_read(LAPIC_ICR_LOW).get_bit(ICR_DELIVERY_PENDING) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Starts the CPU of APIC_ID in real mode at physical address `PAGE << 12`.
+pub fn lapic_send_startup(apic_id: u8, page: u8) {
+    lapic_send_ipi(apic_id, ICR_STARTUP | page as u32);
+}
+
+pub fn lapic_id() -> u8 {
+    (lapic_read(LAPIC_ID) >> 24) as u8
+}
+
+/// Acknowledges the interrupt being serviced.
+pub fn lapic_end_of_interrupt() {
+    lapic_write(LAPIC_EOI, 0);
+}
+
+/// Starts the timer counting down from COUNT, raising `LAPIC_TIMER_VECTOR` when it
+/// reaches 0, and then again every COUNT if PERIODIC.
+pub fn lapic_timer_start(count: u32, periodic: bool) {
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    lapic_write(
+        LAPIC_LVT_TIMER,
+        LAPIC_TIMER_VECTOR as u32
+            | if periodic {
+                LVT_TIMER_PERIODIC
+            } else {
+                LVT_TIMER_ONESHOT
+            },
+    );
+    lapic_write(LAPIC_TIMER_INITIAL, count);
+}
+
+pub fn lapic_timer_stop() {
+    lapic_write(LAPIC_LVT_TIMER, 1 << LVT_MASKED);
+    lapic_write(LAPIC_TIMER_INITIAL, 0);
+}
+
+/// Current count of the timer.
+pub fn lapic_timer_current() -> u32 {
+    lapic_read(LAPIC_TIMER_CURRENT)
+}
@@ -0,0 +1,187 @@
+#![allow(dead_code)]
+
+use core::mem::size_of;
+
+use crate::{
+    smp::{self, percpu::PerCpu, MAX_CPUS},
+    utils::singleton::Singleton,
+};
+
+use super::{tss::TaskStateSegment, DescriptorTablePointer, PrivilegeLevel, SegmentSelector};
+
+/// Global descriptor tables of the kernel, one per CPU, replacing the one of the loader.
+///
+/// The kernel code and data segments are flat as in the loader, with the same
+/// selectors, and the user ones are the same segments in ring 3. The TLS segment is
+/// rebased by the scheduler on each switch to the block of the next thread and stays
+/// loaded in GS, see `thread::tls`. The per-CPU segment covers the `PerCpu` of the CPU
+/// and stays loaded in FS. The TSS gives the ring 0 stack, the kernel stack of the
+/// running thread, on interrupts from ring 3.
+
+pub const GDT_KERNEL_CODE: u16 = 1;
+pub const GDT_KERNEL_DATA: u16 = 2;
+pub const GDT_USER_CODE: u16 = 3;
+pub const GDT_USER_DATA: u16 = 4;
+pub const GDT_TLS: u16 = 5;
+pub const GDT_PERCPU: u16 = 6;
+pub const GDT_TSS: u16 = 7;
+const GDT_ENTRIES: usize = 8;
+
+pub const KERNEL_CODE_SELECTOR: SegmentSelector =
+    SegmentSelector::new(GDT_KERNEL_CODE, PrivilegeLevel::Ring0);
+pub const KERNEL_DATA_SELECTOR: SegmentSelector =
+    SegmentSelector::new(GDT_KERNEL_DATA, PrivilegeLevel::Ring0);
+pub const USER_CODE_SELECTOR: SegmentSelector =
+    SegmentSelector::new(GDT_USER_CODE, PrivilegeLevel::Ring3);
+pub const USER_DATA_SELECTOR: SegmentSelector =
+    SegmentSelector::new(GDT_USER_DATA, PrivilegeLevel::Ring3);
+pub const TLS_SELECTOR: SegmentSelector = SegmentSelector::new(GDT_TLS, PrivilegeLevel::Ring0);
+pub const PERCPU_SELECTOR: SegmentSelector =
+    SegmentSelector::new(GDT_PERCPU, PrivilegeLevel::Ring0);
+pub const TSS_SELECTOR: SegmentSelector = SegmentSelector::new(GDT_TSS, PrivilegeLevel::Ring0);
+
+/// Present, ring 0, execute/read code segment.
+const ACCESS_KERNEL_CODE: u8 = 0x9a;
+/// Present, ring 0, read/write data segment.
+const ACCESS_KERNEL_DATA: u8 = 0x92;
+/// Present, ring 3, execute/read code segment.
+const ACCESS_USER_CODE: u8 = 0xfa;
+/// Present, ring 3, read/write data segment.
+const ACCESS_USER_DATA: u8 = 0xf2;
+/// Present, available 32-bit TSS.
+const ACCESS_TSS: u8 = 0x89;
+/// 32-bit segment with a limit in 4 KiB pages.
+const FLAGS_PAGES: u8 = 0xc;
+/// 32-bit segment with a limit in bytes.
+const FLAGS_BYTES: u8 = 0x4;
+
+/// Encodes a segment descriptor, LIMIT is the last valid offset in the unit of FLAGS.
+pub const fn segment_descriptor(base: u32, limit: u32, access: u8, flags: u8) -> u64 {
+    (limit as u64 & 0xffff)
+        | (base as u64 & 0xff_ffff) << 16
+        | (access as u64) << 40
+        | (limit as u64 >> 16 & 0xf) << 48
+        | (flags as u64 & 0xf) << 52
+        | (base as u64 >> 24) << 56
+}
+
+#[repr(C, align(8))]
+pub struct GlobalDescriptorTable {
+    entries: [u64; GDT_ENTRIES],
+}
+
+impl Default for GlobalDescriptorTable {
+    fn default() -> Self {
+        let mut entries = [0; GDT_ENTRIES];
+        entries[GDT_KERNEL_CODE as usize] =
+            segment_descriptor(0, 0xfffff, ACCESS_KERNEL_CODE, FLAGS_PAGES);
+        entries[GDT_KERNEL_DATA as usize] =
+            segment_descriptor(0, 0xfffff, ACCESS_KERNEL_DATA, FLAGS_PAGES);
+        entries[GDT_USER_CODE as usize] =
+            segment_descriptor(0, 0xfffff, ACCESS_USER_CODE, FLAGS_PAGES);
+        entries[GDT_USER_DATA as usize] =
+            segment_descriptor(0, 0xfffff, ACCESS_USER_DATA, FLAGS_PAGES);
+        entries[GDT_TLS as usize] = segment_descriptor(0, 0, ACCESS_KERNEL_DATA, FLAGS_BYTES);
+        Self { entries }
+    }
+}
+
+impl GlobalDescriptorTable {
+    pub fn set(&mut self, index: u16, descriptor: u64) {
+        self.entries[index as usize] = descriptor;
+    }
+
+    pub fn update(&'static self) {
+        let pgdt = DescriptorTablePointer {
+            base: self as *const _ as u32,
+            limit: (size_of::<Self>() - 1) as u16,
+        };
+        super::lgdt(&pgdt);
+    }
+}
+
+pub static GDT: Singleton<[GlobalDescriptorTable; MAX_CPUS]> = Singleton::UNINIT;
+
+pub static TSS: Singleton<[TaskStateSegment; MAX_CPUS]> = Singleton::UNINIT;
+
+/// Loads the GDT of the bootstrap processor.
+pub fn init() {
+    init_cpu(0);
+}
+
+/// Loads the GDT of CPU and reloads every segment register and the task register. GS is
+/// left on an empty TLS segment until the first thread block is set.
+///
+/// Runs first on each CPU, nothing per-CPU may be used before.
+pub fn init_cpu(cpu: usize) {
+    let gdt = &mut GDT.get_mut()[cpu];
+    gdt.set(
+        GDT_PERCPU,
+        segment_descriptor(
+            smp::percpu::cpu(cpu) as *const PerCpu as u32,
+            size_of::<PerCpu>() as u32 - 1,
+            ACCESS_KERNEL_DATA,
+            FLAGS_BYTES,
+        ),
+    );
+    gdt.set(
+        GDT_TSS,
+        segment_descriptor(
+            &TSS.get_mut()[cpu] as *const TaskStateSegment as u32,
+            size_of::<TaskStateSegment>() as u32 - 1,
+            ACCESS_TSS,
+            0,
+        ),
+    );
+    gdt.update();
+    super::load_segments(KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR);
+    super::load_fs(PERCPU_SELECTOR);
+    super::load_gs(TLS_SELECTOR);
+    super::ltr(TSS_SELECTOR);
+    smp::percpu::init(cpu);
+}
+
+/// Makes the TLS segment of the running CPU cover the SIZE bytes at BASE and reloads GS.
+/// Interrupts must be disabled.
+pub fn set_tls(base: u32, size: u32) {
+    GDT.get_mut()[smp::cpu_id()].set(
+        GDT_TLS,
+        segment_descriptor(base, size - 1, ACCESS_KERNEL_DATA, FLAGS_BYTES),
+    );
+    super::load_gs(TLS_SELECTOR);
+}
+
+/// Sets the stack the running CPU switches to on interrupts from ring 3, TOP being its
+/// highest address. Interrupts must be disabled.
+pub fn set_kernel_stack(top: u32) {
+    TSS.get_mut()[smp::cpu_id()].esp0 = top;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_segments_match_the_loader() {
+        let kernel_code = segment_descriptor(0, 0xfffff, ACCESS_KERNEL_CODE, FLAGS_PAGES);
+        let kernel_data = segment_descriptor(0, 0xfffff, ACCESS_KERNEL_DATA, FLAGS_PAGES);
+        assert_eq!(kernel_code, 0x00cf_9a00_0000_ffff);
+        assert_eq!(kernel_data, 0x00cf_9200_0000_ffff);
+    }
+
+    #[test]
+    fn user_segments_only_differ_by_privilege_level() {
+        let user_code = segment_descriptor(0, 0xfffff, ACCESS_USER_CODE, FLAGS_PAGES);
+        let user_data = segment_descriptor(0, 0xfffff, ACCESS_USER_DATA, FLAGS_PAGES);
+        assert_eq!(user_code, 0x00cf_fa00_0000_ffff);
+        assert_eq!(user_data, 0x00cf_f200_0000_ffff);
+    }
+
+    #[test]
+    fn base_and_limit_are_split_across_the_descriptor() {
+        let tss = segment_descriptor(0xc012_3456, 0x67, ACCESS_TSS, 0);
+        assert_eq!(tss, 0xc000_8912_3456_0067);
+        let tls = segment_descriptor(0x1234_5678, 0x1_2345, ACCESS_KERNEL_DATA, FLAGS_BYTES);
+        assert_eq!(tls, 0x1241_9234_5678_2345);
+    }
+}
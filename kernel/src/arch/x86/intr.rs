@@ -1,11 +1,13 @@
 #![allow(dead_code)]
 
+use core::arch::global_asm;
 use core::marker::PhantomData;
 use core::mem::size_of;
 use core::ops::{Index, IndexMut};
-use core::ptr::addr_of_mut;
+use core::ptr::{addr_of, addr_of_mut};
 
 use crate::arch::x86;
+use crate::arch::x86::gdt::{PERCPU_SELECTOR, TLS_SELECTOR, USER_DATA_SELECTOR};
 use crate::utils::singleton::Singleton;
 use crate::utils::BitAccess;
 
@@ -18,6 +20,65 @@ type DivergingHandlerFuncErrCode = extern "x86-interrupt" fn(_: ExceptionStackFr
 
 pub static INTR_TABLE: Singleton<InterruptDescriptorTable> = Singleton::UNINIT;
 
+/// Entry stubs.
+///
+/// Each gate points to the stub of its vector rather than to the handler. An interrupt
+/// from ring 3 leaves the segment registers as user code had them, and the return to
+/// ring 3 of the previous interrupt nulled FS and GS, which hold ring 0 segments. The
+/// stub reloads them when the saved CS is a ring 3 one, then jumps to the handler of
+/// the vector in `INTR_HANDLERS`. DS and ES get the user data segment, flat as the
+/// kernel one, so that they are valid again on the way back to ring 3. User code cannot
+/// keep values of its own in FS and GS across interrupts.
+
+/// Bytes between two stubs.
+const INTR_STUB_SIZE: usize = 32;
+
+/// Handler of each vector, jumped to by its stub.
+#[no_mangle]
+static mut INTR_HANDLERS: [u32; 256] = [0; 256];
+
+global_asm!(
+    ".global intr_stubs",
+    ".global intr_load_kernel_segments",
+    ".balign {stub_size}",
+    "intr_stubs:",
+    ".set intr_vector, 0",
+    ".rept 256",
+    // The CPU pushed an error code below the frame.
+    ".if (intr_vector == 8) | ((intr_vector >= 10) & (intr_vector <= 14)) | (intr_vector == 17) | (intr_vector == 21) | (intr_vector == 29) | (intr_vector == 30)",
+    "    test byte ptr [esp + 8], 3",
+    ".else",
+    "    test byte ptr [esp + 4], 3",
+    ".endif",
+    "    jz 2f",
+    "    call intr_load_kernel_segments",
+    "2:",
+    "    jmp dword ptr ds:[INTR_HANDLERS + intr_vector * 4]",
+    ".balign {stub_size}",
+    ".set intr_vector, intr_vector + 1",
+    ".endr",
+    // Also called by the SYSENTER entry, which leaves the segments as well.
+    "intr_load_kernel_segments:",
+    "    push eax",
+    "    mov eax, {user_data}",
+    "    mov ds, ax",
+    "    mov es, ax",
+    "    mov eax, {percpu}",
+    "    mov fs, ax",
+    "    mov eax, {tls}",
+    "    mov gs, ax",
+    "    pop eax",
+    "    ret",
+    stub_size = const INTR_STUB_SIZE,
+    user_data = const USER_DATA_SELECTOR.0,
+    percpu = const PERCPU_SELECTOR.0,
+    tls = const TLS_SELECTOR.0,
+);
+
+extern "C" {
+    static intr_stubs: u8;
+}
+
 #[repr(C)]
 #[repr(align(16))]
 pub struct InterruptDescriptorTable {
@@ -477,13 +538,34 @@ impl<T> InterruptEntry<T> {
         self
     }
 
+    /// Vector of the entry, which must be in `INTR_TABLE`.
+    fn vector(&self) -> usize {
+        let offset = self as *const Self as usize - &*INTR_TABLE as *const _ as usize;
+        assert!(offset < size_of::<InterruptDescriptorTable>());
+        offset / size_of::<Self>()
+    }
+
+    /// Makes the handler at POINTER handle the vector, through its stub. Only ring 0
+    /// may raise it with `int`, see `set_privilege_level`.
     pub fn set_handle_addr(&mut self, pointer: usize) -> &mut Self {
-        self.pointer_low = (pointer & 0xffff) as u16;
-        self.pointer_middle = (pointer >> 16) as u16;
+        let vector = self.vector();
+        unsafe { INTR_HANDLERS[vector] = pointer as u32 };
+        let stub = addr_of!(intr_stubs) as usize + vector * INTR_STUB_SIZE;
+        self.pointer_low = (stub & 0xffff) as u16;
+        self.pointer_middle = (stub >> 16) as u16;
         self.gdt_selector = x86::get_cs();
         self.set_option(InterruptOption::new(false, PrivilegeLevel::Ring0, true));
         self
     }
+
+    /// Sets the least privileged ring allowed to raise the vector with `int`, which
+    /// raises a general protection fault otherwise. Interrupts and exceptions raised by
+    /// the CPU are delivered whatever the level.
+    pub fn set_privilege_level(&mut self, level: PrivilegeLevel) -> &mut Self {
+        let mut opt = unsafe { addr_of!(self.options).read_unaligned() };
+        opt.set_privilege_level(level as u16);
+        self.set_option(opt)
+    }
 }
 
 impl InterruptEntry<HandlerFunc> {
@@ -567,6 +649,8 @@ impl Default for InterruptOption {
     }
 }
 
+/// Frame pushed by the CPU. The stack pointer and segment are only pushed on a switch
+/// from ring 3.
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct ExceptionStackFrame {
@@ -577,6 +661,9 @@ pub struct ExceptionStackFrame {
     pub stack_segment: u16,
 }
 
-pub fn end_of_interrupt() {
-    x86::outb(0x20, 0x20);
+impl ExceptionStackFrame {
+    /// The interrupted code ran in ring 3.
+    pub fn is_user_mode(&self) -> bool {
+        self.code_segment & 3 == PrivilegeLevel::Ring3 as u16
+    }
 }
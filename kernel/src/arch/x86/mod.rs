@@ -2,10 +2,14 @@
 
 use core::{arch::asm, fmt};
 
-use crate::utils::BitAccess;
+use crate::{smp::klock, utils::BitAccess};
 
+pub mod apic;
+pub mod gdt;
 pub mod intr;
 pub mod pic;
+pub mod switch;
+pub mod tss;
 
 pub fn inb(port: u16) -> u8 {
     let mut data: u8;
@@ -29,6 +33,58 @@ pub fn cr3() -> u32 {
     return addr;
 }
 
+/// Loads the page directory at physical address DIR, flushing the TLB.
+pub fn set_cr3(dir: u32) {
+    unsafe {
+        asm!("mov cr3, {:e}", in(reg) dir, options(nostack, preserves_flags));
+    }
+}
+
+/// Linear address of the last page fault.
+pub fn cr2() -> u32 {
+    let mut addr: u32;
+    unsafe {
+        asm!("mov eax, cr2", out("eax") addr, options(nomem, nostack, preserves_flags));
+    }
+    addr
+}
+
+/// CR0 task switched flag: the next FPU or SSE instruction raises `#NM`.
+pub const CR0_TS: usize = 3;
+
+pub fn cr0() -> u32 {
+    let mut val: u32;
+    unsafe {
+        asm!("mov eax, cr0", out("eax") val, options(nomem, nostack, preserves_flags));
+    }
+    val
+}
+
+pub fn set_cr0(val: u32) {
+    unsafe {
+        asm!("mov cr0, eax", in("eax") val, options(nostack, preserves_flags));
+    }
+}
+
+/// Clears CR0.TS.
+pub fn clts() {
+    unsafe {
+        asm!("clts", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Sets CR0.TS.
+pub fn stts() {
+    set_cr0(*cr0().set_bit(CR0_TS, true));
+}
+
+/// Invalidates the TLB entry of the page containing ADDR.
+pub fn invlpg(addr: usize) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) addr, options(nostack, preserves_flags));
+    }
+}
+
 pub fn esp() -> u32 {
     let mut addr: u32;
     unsafe {
@@ -37,19 +93,167 @@ pub fn esp() -> u32 {
     return addr;
 }
 
+pub fn eflags() -> u32 {
+    let mut flags: u32;
+    unsafe {
+        asm!("pushfd", "pop eax", out("eax") flags, options(nomem, preserves_flags));
+    }
+    flags
+}
 
+/// Enables interrupts, leaving the critical section entered by `cli`.
 pub fn sti() {
+    klock::release();
     unsafe {
         asm!("sti")
     }
 }
 
-pub fn cti() {
+/// Disables interrupts, which delimits the critical sections of the kernel. With several
+/// CPUs this also takes the kernel lock, see `smp::klock`.
+pub fn cli() {
+    unsafe {
+        asm!("cli")
+    }
+    klock::acquire();
+}
+
+pub fn hlt() {
+    unsafe {
+        asm!("hlt")
+    }
+}
+
+/// Enables interrupts and halts until the next one. `sti` only takes effect after the
+/// following instruction, so no interrupt can slip in before the `hlt`.
+pub fn sti_hlt() {
+    klock::release();
+    unsafe {
+        asm!("sti", "hlt")
+    }
+}
+
+/// Returns whether maskable interrupts are enabled (`EFLAGS.IF`).
+pub fn intr_enabled() -> bool {
+    eflags().get_bit(9)
+}
+
+/// Runs `f` with maskable interrupts disabled, restoring the previous state afterwards.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = intr_enabled();
+    cli();
+    let ret = f();
+    if enabled {
+        sti();
+    }
+    ret
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Executes `CPUID` for LEAF and SUBLEAF.
+///
+/// EBX is saved by hand since LLVM may reserve it.
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    let (eax, ebx, ecx, edx);
+    unsafe {
+        asm!(
+            "mov {tmp:e}, ebx",
+            "cpuid",
+            "xchg {tmp:e}, ebx",
+            tmp = out(reg) ebx,
+            inout("eax") leaf => eax,
+            inout("ecx") subleaf => ecx,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    CpuidResult { eax, ebx, ecx, edx }
+}
+
+/// CPUID leaf 1 EDX: time stamp counter.
+pub const CPUID_1_EDX_TSC: usize = 4;
+/// CPUID leaf 0x8000_0007 EDX: the TSC runs at a constant rate in every P/C-state.
+pub const CPUID_80000007_EDX_INVARIANT_TSC: usize = 8;
+
+pub const CPUID_1_EDX_FXSR: usize = 24;
+
+/// Whether FXSAVE and FXRSTOR are supported.
+pub fn has_fxsr() -> bool {
+    cpuid(1, 0).edx.get_bit(CPUID_1_EDX_FXSR)
+}
+
+/// CPUID leaf 1 EDX: SYSENTER and SYSEXIT.
+pub const CPUID_1_EDX_SEP: usize = 11;
+
+/// Whether SYSENTER and SYSEXIT are supported. The first Pentium Pro steppings report
+/// them without supporting them.
+pub fn has_sep() -> bool {
+    let leaf1 = cpuid(1, 0);
+    let family = leaf1.eax.get_bits(8..=11);
+    let model = leaf1.eax.get_bits(4..=7);
+    let stepping = leaf1.eax.get_bits(0..=3);
+    leaf1.edx.get_bit(CPUID_1_EDX_SEP) && !(family == 6 && model < 3 && stepping < 3)
+}
+
+pub fn has_tsc() -> bool {
+    cpuid(1, 0).edx.get_bit(CPUID_1_EDX_TSC)
+}
+
+pub fn has_invariant_tsc() -> bool {
+    cpuid(0x8000_0000, 0).eax >= 0x8000_0007
+        && cpuid(0x8000_0007, 0)
+            .edx
+            .get_bit(CPUID_80000007_EDX_INVARIANT_TSC)
+}
+
+pub const IA32_SYSENTER_CS: u32 = 0x174;
+pub const IA32_SYSENTER_ESP: u32 = 0x175;
+pub const IA32_SYSENTER_EIP: u32 = 0x176;
+
+/// Reads model specific register MSR.
+pub fn rdmsr(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    unsafe {
+        asm!(
+            "rdmsr",
+            in("ecx") msr,
+            out("eax") lo,
+            out("edx") hi,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Writes VALUE to model specific register MSR.
+pub fn wrmsr(msr: u32, value: u64) {
     unsafe {
-        asm!("cti")
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags),
+        );
     }
 }
 
+/// Reads the time stamp counter.
+pub fn rdtsc() -> u64 {
+    let (lo, hi): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack, preserves_flags));
+    }
+    (hi as u64) << 32 | lo as u64
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
 pub struct DescriptorTablePointer {
@@ -65,6 +269,54 @@ pub fn lidt(idt: &DescriptorTablePointer) {
     }
 }
 
+pub fn lgdt(gdt: &DescriptorTablePointer) {
+    unsafe {
+        asm!("lgdt [{}]", in(reg) gdt, options(readonly, nostack, preserves_flags));
+    }
+}
+
+/// Loads CS with a far return and the data segment registers but GS with DATA.
+pub fn load_segments(code: SegmentSelector, data: SegmentSelector) {
+    unsafe {
+        asm!(
+            "push {code:e}",
+            "lea eax, [2f]",
+            "push eax",
+            "retf",
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov fs, {data:x}",
+            "mov ss, {data:x}",
+            code = in(reg) code.0 as u32,
+            data = in(reg) data.0 as u32,
+            out("eax") _,
+            options(preserves_flags),
+        );
+    }
+}
+
+/// Loads FS, which also reloads the cached descriptor.
+pub fn load_fs(selector: SegmentSelector) {
+    unsafe {
+        asm!("mov fs, {0:x}", in(reg) selector.0 as u32, options(nostack, preserves_flags));
+    }
+}
+
+/// Loads the task register, marking the TSS busy.
+pub fn ltr(selector: SegmentSelector) {
+    unsafe {
+        asm!("ltr {0:x}", in(reg) selector.0 as u32, options(nostack, preserves_flags));
+    }
+}
+
+/// Loads GS, which also reloads the cached descriptor.
+pub fn load_gs(selector: SegmentSelector) {
+    unsafe {
+        asm!("mov gs, {0:x}", in(reg) selector.0 as u32, options(nostack, preserves_flags));
+    }
+}
+
 /// Represents a protection ring level.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u8)]
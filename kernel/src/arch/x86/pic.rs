@@ -1,4 +1,7 @@
-use crate::arch::x86::outb;
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+use crate::arch::x86::{inb, outb, without_interrupts};
+use crate::utils::BitAccess;
 
 /// Programmable Interrupt Controller (PIC) registers.
 /// A PC has two PICs, called the master and slave PICs, with the
@@ -16,6 +19,25 @@ const PIC1_CTRL: u16 = 0xa0;
 /// Slave PIC data register address.
 const PIC1_DATA: u16 = 0xa1;
 
+/// Vector of IRQ line 0, lines 0...15 are mapped to vectors 0x20...0x2f.
+pub const IRQ_VECTOR_BASE: u8 = 0x20;
+
+/// OCW2 non-specific end of interrupt.
+const PIC_EOI: u8 = 0x20;
+
+/// OCW3 commands selecting the register returned by the next read of the control port.
+const PIC_READ_IRR: u8 = 0x0a;
+const PIC_READ_ISR: u8 = 0x0b;
+
+/// Lines disabled by their drivers.
+static PIC_DISABLED: AtomicU16 = AtomicU16::new(0);
+
+/// Lines masked by the current interrupt level.
+static PIC_LEVEL_MASK: AtomicU16 = AtomicU16::new(0);
+
+/// Last value written to the interrupt mask registers.
+static PIC_IMR: AtomicU16 = AtomicU16::new(0);
+
 pub fn pic_init() {
     outb(PIC0_DATA, 0xff);
     outb(PIC1_DATA, 0xff);
@@ -35,6 +57,87 @@ pub fn pic_init() {
     /* Unmask all interrupts. */
     outb(PIC0_DATA, 0x00);
     outb(PIC1_DATA, 0x00);
+    PIC_IMR.store(0, Ordering::Relaxed);
+    pic_update_imr();
+}
+
+fn pic_update_imr() {
+    without_interrupts(|| {
+        let mask = PIC_DISABLED.load(Ordering::Relaxed) | PIC_LEVEL_MASK.load(Ordering::Relaxed);
+        let old = PIC_IMR.swap(mask, Ordering::Relaxed);
+        if old as u8 != mask as u8 {
+            outb(PIC0_DATA, mask as u8);
+        }
+        if (old >> 8) as u8 != (mask >> 8) as u8 {
+            outb(PIC1_DATA, (mask >> 8) as u8);
+        }
+    })
+}
+
+/// Masks IRQ regardless of the interrupt level.
+pub fn pic_disable_irq(irq: u8) {
+    PIC_DISABLED.fetch_or(1 << irq, Ordering::Relaxed);
+    pic_update_imr();
+}
+
+/// Undoes `pic_disable_irq`.
+pub fn pic_enable_irq(irq: u8) {
+    PIC_DISABLED.fetch_and(!(1 << irq), Ordering::Relaxed);
+    pic_update_imr();
+}
+
+/// Sets the lines masked on behalf of the interrupt level, see `irq::level`.
+pub fn pic_set_level_mask(mask: u16) {
+    PIC_LEVEL_MASK.store(mask, Ordering::Relaxed);
+    pic_update_imr();
+}
+
+fn pic_read_reg(ocw3: u8) -> u16 {
+    outb(PIC0_CTRL, ocw3);
+    outb(PIC1_CTRL, ocw3);
+    ((inb(PIC1_CTRL) as u16) << 8) | inb(PIC0_CTRL) as u16
+}
+
+/// Interrupt request register, the lines raised but not yet delivered.
+/// Bits 0...7 are the master lines and bits 8...15 the slave lines.
+pub fn pic_read_irr() -> u16 {
+    pic_read_reg(PIC_READ_IRR)
+}
+
+/// In-service register, the lines delivered to the CPU but not yet acknowledged.
+pub fn pic_read_isr() -> u16 {
+    pic_read_reg(PIC_READ_ISR)
+}
+
+/// Checks whether the interrupt just received on IRQ is spurious.
+///
+/// When a request disappears before the PIC can deliver it (noise on the line, or the
+/// line masked in between), the PIC still signals the lowest priority line of the chip,
+/// IRQ 7 or IRQ 15, without setting its ISR bit. Such interrupts must not be
+/// acknowledged, except that a spurious IRQ 15 was a real IRQ 2 for the master, which
+/// needs its EOI.
+pub fn pic_is_spurious(irq: u8) -> bool {
+    match irq {
+        7 => !pic_read_isr().get_bit(7),
+        15 => {
+            if pic_read_isr().get_bit(15) {
+                false
+            } else {
+                outb(PIC0_CTRL, PIC_EOI);
+                true
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Acknowledges IRQ, the slave needs its own EOI besides the master's one.
+pub fn pic_end_of_interrupt(irq: u8) {
+    assert!(irq < 16);
+    if irq >= 8 {
+        outb(PIC1_CTRL, PIC_EOI);
+    }
+    outb(PIC0_CTRL, PIC_EOI);
 }
 
 /* Interface to 8254 Programmable Interrupt Timer (PIT).
@@ -44,8 +147,15 @@ Refer to [8254] for details. */
 const PIT_PORT_CONTROL: u16 = 0x43; /* Control port. */
 const PIT_PORT_COUNTER_OFFSET_TO_CHANNEL: u16 = 0x40; /* Counter port. */
 
+/* Keyboard controller port B: bit 0 is the gate of channel 2,
+bit 1 connects channel 2 to the speaker. */
+const PIT_PORT_CHANNEL2_GATE: u16 = 0x61;
+
 /* PIT cycles per second. */
-const PIT_HZ: u32 = 1193180;
+pub const PIT_HZ: u32 = 1193180;
+
+/* Reload value of each channel, 65536 for a count of 0. */
+static PIT_RELOAD: [AtomicU32; 3] = [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)];
 
 /* Configure the given CHANNEL in the PIT.  In a PC, the PIT's
 three output channels are hooked up like this:
@@ -114,4 +224,46 @@ pub fn pit_configure_channel(channel: u16, mode: u16, frequency: u32) {
         (count >> 8) as u8,
     );
     // intr_set_level(old_level);
+    PIT_RELOAD[channel as usize].store(
+        if count == 0 { 0x10000 } else { count as u32 },
+        Ordering::Relaxed,
+    );
+}
+
+/* Reads the current count of CHANNEL.  The counter latch
+command freezes the value so that both bytes belong to the
+same count. */
+pub fn pit_read_counter(channel: u16) -> u16 {
+    assert!(channel == 0 || channel == 2);
+    without_interrupts(|| {
+        outb(PIT_PORT_CONTROL, (channel << 6) as u8);
+        let lo = inb(PIT_PORT_COUNTER_OFFSET_TO_CHANNEL + channel) as u16;
+        let hi = inb(PIT_PORT_COUNTER_OFFSET_TO_CHANNEL + channel) as u16;
+        hi << 8 | lo
+    })
+}
+
+/* Sets the gate of channel 2, which only counts while it is
+high, with the speaker disconnected. */
+pub fn pit_channel2_gate(enable: bool) {
+    let val = inb(PIT_PORT_CHANNEL2_GATE) & !0x03;
+    outb(PIT_PORT_CHANNEL2_GATE, val | enable as u8);
+}
+
+/* Returns the reload value CHANNEL was last configured with,
+or 0 if it has never been configured. */
+pub fn pit_reload(channel: u16) -> u32 {
+    PIT_RELOAD[channel as usize].load(Ordering::Relaxed)
+}
+
+/* Number of PIT cycles between two counter reads START and END
+of the periodic CHANNEL, assuming less than one period passed. */
+pub fn pit_elapsed(channel: u16, start: u16, end: u16) -> u32 {
+    let reload = pit_reload(channel);
+    let (start, end) = (start as u32, end as u32);
+    if start >= end {
+        start - end
+    } else {
+        start + reload - end
+    }
 }
@@ -0,0 +1,75 @@
+#![allow(dead_code)]
+
+use core::arch::global_asm;
+
+// Kernel thread context switch.
+//
+// A thread switched out keeps its callee-saved registers and return address on its own
+// stack, so the only state to store elsewhere is its stack pointer.
+
+extern "C" {
+    /// Saves the callee-saved registers on the current stack and the stack pointer at
+    /// `*cur`, then loads the stack pointer from `*next` and restores the registers of
+    /// that context.
+    ///
+    /// Returns, in the context of NEXT, the CUR argument of the call that switched to it.
+    pub fn switch_context(cur: *mut usize, next: *mut usize) -> *mut usize;
+
+    /// First return address of a new context, see `prepare_stack`.
+    fn switch_entry();
+}
+
+global_asm!(
+    ".global switch_context",
+    "switch_context:",
+    "    mov eax, [esp + 4]",
+    "    mov edx, [esp + 8]",
+    "    push ebp",
+    "    push ebx",
+    "    push esi",
+    "    push edi",
+    "    mov [eax], esp",
+    "    mov esp, [edx]",
+    "    pop edi",
+    "    pop esi",
+    "    pop ebx",
+    "    pop ebp",
+    "    ret",
+    "",
+    ".global switch_entry",
+    "switch_entry:",
+    "    and esp, -16",
+    "    sub esp, 12",
+    "    push eax",
+    "    call ebx",
+    "    ud2",
+);
+
+/// Registers popped by `switch_context`, lowest address first.
+#[repr(C)]
+struct SwitchFrame {
+    edi: u32,
+    esi: u32,
+    ebx: u32,
+    ebp: u32,
+    eip: u32,
+}
+
+/// Builds on the empty stack ending at TOP a context that, once switched to, calls
+/// `start(prev)` on an aligned stack, PREV being the context switched away from.
+/// Returns the stack pointer to hand to `switch_context`.
+///
+/// # Safety
+///
+/// TOP must be the end of writable memory with room for the frame.
+pub unsafe fn prepare_stack(top: usize, start: extern "C" fn(prev: *mut usize) -> !) -> usize {
+    let frame = (top - core::mem::size_of::<SwitchFrame>()) as *mut SwitchFrame;
+    frame.write(SwitchFrame {
+        edi: 0,
+        esi: 0,
+        ebx: start as *const () as u32,
+        ebp: 0,
+        eip: switch_entry as *const () as u32,
+    });
+    frame as usize
+}
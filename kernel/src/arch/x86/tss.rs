@@ -0,0 +1,54 @@
+#![allow(dead_code)]
+
+use core::mem::size_of;
+
+use super::gdt::KERNEL_DATA_SELECTOR;
+
+/// 32-bit task state segment.
+///
+/// The kernel does not switch tasks in hardware, each CPU has one TSS only to give the
+/// stack to switch to when an interrupt arrives in a less privileged ring.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TaskStateSegment {
+    link: u32,
+    /// Stack pointer loaded on a switch to ring 0.
+    pub esp0: u32,
+    /// Stack segment loaded on a switch to ring 0.
+    pub ss0: u32,
+    esp1: u32,
+    ss1: u32,
+    esp2: u32,
+    ss2: u32,
+    cr3: u32,
+    eip: u32,
+    eflags: u32,
+    regs: [u32; 8],
+    segments: [u32; 6],
+    ldt: u32,
+    trap: u16,
+    /// Offset of the I/O permission bitmap, past the limit when there is none.
+    iomap_base: u16,
+}
+
+impl Default for TaskStateSegment {
+    fn default() -> Self {
+        Self {
+            link: 0,
+            esp0: 0,
+            ss0: KERNEL_DATA_SELECTOR.0 as u32,
+            esp1: 0,
+            ss1: 0,
+            esp2: 0,
+            ss2: 0,
+            cr3: 0,
+            eip: 0,
+            eflags: 0,
+            regs: [0; 8],
+            segments: [0; 6],
+            ldt: 0,
+            trap: 0,
+            iomap_base: size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
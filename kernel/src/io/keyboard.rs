@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+
+use core::{future::poll_fn, task::Poll};
+
+use crate::{
+    arch::x86::{
+        inb,
+        intr::{ExceptionStackFrame, INTR_TABLE},
+        pic::IRQ_VECTOR_BASE,
+        without_interrupts,
+    },
+    irq, print,
+    sync::WaitQueue,
+    task::WakerSlot,
+    utils::{ring::RingBuffer, singleton::Singleton},
+};
+
+/// IRQ line of the PS/2 keyboard.
+const KEYBOARD_IRQ: u8 = 1;
+
+const KEYBOARD_DATA: u16 = 0x60;
+
+/// Characters typed and not read yet.
+static KEYBOARD_INPUT: Singleton<RingBuffer<char, 64>> = Singleton::UNINIT;
+
+/// Threads waiting in `read_char`.
+static KEYBOARD_READERS: WaitQueue = WaitQueue::new();
+
+/// Task waiting in `read_char_async`.
+static KEYBOARD_WAKER: WakerSlot = WakerSlot::new();
+
+pub fn init() {
+    INTR_TABLE.get_mut()[(IRQ_VECTOR_BASE + KEYBOARD_IRQ) as usize].set_handle_fn(keyboard_handler);
+}
+
+extern "x86-interrupt" fn keyboard_handler(_f: ExceptionStackFrame) {
+    let Some(_irq) = irq::irq_enter(KEYBOARD_IRQ) else {
+        return;
+    };
+    let scancode = inb(KEYBOARD_DATA);
+    irq::work::schedule_work(keyboard_work, scancode as usize);
+}
+
+/// Echoes the typed character and hands it to the readers.
+fn keyboard_work(scancode: usize) {
+    if let Some(ch) = scancode_to_char(scancode as u8) {
+        print!("{}", ch);
+        without_interrupts(|| KEYBOARD_INPUT.get_mut().push(ch));
+        KEYBOARD_READERS.wake_all();
+        KEYBOARD_WAKER.wake();
+    }
+}
+
+/// Reads a typed character, sleeping until there is one.
+pub fn read_char() -> char {
+    let mut ch = None;
+    KEYBOARD_READERS.wait_event(|| {
+        ch = KEYBOARD_INPUT.get_mut().pop();
+        ch.is_some()
+    });
+    ch.unwrap()
+}
+
+/// Reads a typed character from a task, there must be only one such reader at a time.
+pub async fn read_char_async() -> char {
+    poll_fn(|cx| {
+        KEYBOARD_WAKER.register(cx.waker());
+        match try_read_char() {
+            Some(ch) => Poll::Ready(ch),
+            None => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Reads a typed character if there is one.
+pub fn try_read_char() -> Option<char> {
+    without_interrupts(|| KEYBOARD_INPUT.get_mut().pop())
+}
+
+pub fn scancode_to_char(code: u8) -> Option<char> {
+    // println!("scancode {}", code);
+    match code {
+        0x00 => {
+            panic!("Error Scancode 0x00")
+        }
+        0x02..=0x0a => Some((b'0' + code - 1) as char),
+        0x0b => Some('0'),
+        0x10..=0x19 => {
+            Some(['q', 'w', 'e', 'r', 't', 'y', 'u', 'i', 'o', 'p'][code as usize - 0x10])
+        }
+        0x1c => Some('\n'),
+        0x0e => Some(0x08 as char),
+        0x1e..=0x26 => Some(['a', 's', 'd', 'f', 'g', 'h', 'j', 'k', 'l'][code as usize - 0x1e]),
+        0x2c..=0x32 => Some(['z', 'x', 'c', 'v', 'b', 'n', 'm'][code as usize - 0x2c]),
+        0x39 => Some(' '),
+        0x80.. => None,
+        _ => Some('?'),
+    }
+}
@@ -1,2 +1,4 @@
+pub mod keyboard;
+pub mod rtc;
 pub mod serial;
 pub mod vga;
@@ -0,0 +1,312 @@
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use crate::{
+    arch::x86::{
+        inb,
+        intr::{ExceptionStackFrame, INTR_TABLE},
+        outb,
+        pic::IRQ_VECTOR_BASE,
+        without_interrupts,
+    },
+    acpi, irq, serial_println,
+    time::{self, TimerId},
+    utils::BitAccess,
+};
+
+/// CMOS real-time clock (MC146818).
+
+const CMOS_ADDR: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Setting bit 7 of the address port disables the NMI while the index is selected.
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_SECONDS_ALARM: u8 = 0x01;
+const REG_MINUTES: u8 = 0x02;
+const REG_MINUTES_ALARM: u8 = 0x03;
+const REG_HOURS: u8 = 0x04;
+const REG_HOURS_ALARM: u8 = 0x05;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+
+/// Status A: update in progress.
+const STATUS_A_UIP: usize = 7;
+/// Status B: periodic, alarm and update-ended interrupt enables.
+const STATUS_B_PIE: usize = 6;
+const STATUS_B_AIE: usize = 5;
+/// Status B: 24-hour mode and binary (not BCD) mode.
+const STATUS_B_24H: usize = 1;
+const STATUS_B_BINARY: usize = 2;
+/// Status C: interrupt flags, reading the register acknowledges them.
+const STATUS_C_PF: usize = 6;
+const STATUS_C_AF: usize = 5;
+
+/// Bit 7 of the hours register is the PM flag in 12-hour mode.
+const HOURS_PM: usize = 7;
+
+/// Alarm registers match any value when holding 0xc0...0xff.
+const ALARM_ANY: u8 = 0xff;
+
+pub const RTC_IRQ: u8 = 8;
+
+/// Index of the century register, which only the ACPI FADT tells, 0 if there is none.
+static REG_CENTURY: AtomicU8 = AtomicU8::new(0);
+
+fn cmos_read(reg: u8) -> u8 {
+    outb(CMOS_ADDR, NMI_DISABLE | reg);
+    inb(CMOS_DATA)
+}
+
+fn cmos_write(reg: u8, val: u8) {
+    outb(CMOS_ADDR, NMI_DISABLE | reg);
+    outb(CMOS_DATA, val);
+}
+
+fn bcd_to_bin(val: u8) -> u8 {
+    (val & 0x0f) + (val >> 4) * 10
+}
+
+fn bin_to_bcd(val: u8) -> u8 {
+    (val / 10) << 4 | (val % 10)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC, the RTC being kept in UTC.
+    pub fn to_unix(self) -> u64 {
+        days_from_civil(self.year as i64, self.month as i64, self.day as i64) as u64 * 86400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, see
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[derive(PartialEq, Eq)]
+struct RawTime([u8; 7]);
+
+fn read_raw() -> RawTime {
+    RawTime([
+        cmos_read(REG_SECONDS),
+        cmos_read(REG_MINUTES),
+        cmos_read(REG_HOURS),
+        cmos_read(REG_DAY),
+        cmos_read(REG_MONTH),
+        cmos_read(REG_YEAR),
+        match REG_CENTURY.load(Ordering::Relaxed) {
+            0 => 0,
+            reg => cmos_read(reg),
+        },
+    ])
+}
+
+fn update_in_progress() -> bool {
+    cmos_read(REG_STATUS_A).get_bit(STATUS_A_UIP)
+}
+
+/// Reads the current date and time.
+///
+/// The registers are read twice after the update in progress flag clears, until two
+/// consecutive reads agree, so that no value is torn by an update happening meanwhile.
+pub fn read_time() -> DateTime {
+    let raw = without_interrupts(|| {
+        let mut last;
+        let mut cur = {
+            while update_in_progress() {}
+            read_raw()
+        };
+        loop {
+            last = cur;
+            while update_in_progress() {}
+            cur = read_raw();
+            if cur == last {
+                break cur;
+            }
+        }
+    });
+    let status_b = without_interrupts(|| cmos_read(REG_STATUS_B));
+    let [mut second, mut minute, mut hour, mut day, mut month, mut year, mut century] = raw.0;
+
+    let pm = hour.get_bit(HOURS_PM);
+    hour.set_bit(HOURS_PM, false);
+    if !status_b.get_bit(STATUS_B_BINARY) {
+        second = bcd_to_bin(second);
+        minute = bcd_to_bin(minute);
+        hour = bcd_to_bin(hour);
+        day = bcd_to_bin(day);
+        month = bcd_to_bin(month);
+        year = bcd_to_bin(year);
+        century = bcd_to_bin(century);
+    }
+    if !status_b.get_bit(STATUS_B_24H) {
+        // 12-hour clock: 12 AM is midnight and 12 PM is noon.
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+    let year = match century {
+        19..=99 => century as u16 * 100 + year as u16,
+        // No century register, assume the 21st century.
+        _ => 2000 + year as u16,
+    };
+
+    DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
+
+static PERIODIC_INTERRUPTS: AtomicU32 = AtomicU32::new(0);
+static mut ALARM_CALLBACK: Option<fn()> = None;
+
+/// Interval at which the interrupt flags are polled while IRQ 8 is taken over, in
+/// milliseconds.
+const POLL_PERIOD_MS: u64 = 100;
+
+/// Timer polling the interrupt flags, armed while IRQ 8 is taken over.
+static mut POLL_TIMER: Option<TimerId> = None;
+
+/// Looks up the century register and installs the IRQ 8 handler.
+pub fn init() {
+    if let Some(reg) = acpi::rtc_century_register() {
+        REG_CENTURY.store(reg, Ordering::Relaxed);
+    }
+    INTR_TABLE.get_mut()[(IRQ_VECTOR_BASE + RTC_IRQ) as usize].set_handle_fn(rtc_handler);
+    without_interrupts(|| {
+        // Discard any interrupt flagged before the handler was installed.
+        cmos_read(REG_STATUS_C);
+    });
+}
+
+/// Enables the periodic interrupt at `32768 >> (rate - 1)` Hz, RATE being 3...15
+/// (8192 Hz down to 2 Hz), or disables it if RATE is 0.
+pub fn set_periodic_rate(rate: u8) {
+    assert!(rate == 0 || (3..=15).contains(&rate));
+    without_interrupts(|| {
+        let status_a = cmos_read(REG_STATUS_A);
+        cmos_write(REG_STATUS_A, (status_a & 0xf0) | rate);
+        let mut status_b = cmos_read(REG_STATUS_B);
+        status_b.set_bit(STATUS_B_PIE, rate != 0);
+        cmos_write(REG_STATUS_B, status_b);
+    })
+}
+
+/// Number of periodic interrupts received.
+pub fn periodic_interrupts() -> u32 {
+    PERIODIC_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// Arms the alarm interrupt to call CALLBACK every day at HOUR:MINUTE:SECOND (UTC),
+/// `None` matching any value. The callback runs in the interrupt handler, or in a timer
+/// callback while the interrupt flags are polled.
+pub fn set_alarm(hour: Option<u8>, minute: Option<u8>, second: Option<u8>, callback: fn()) {
+    without_interrupts(|| {
+        let mut status_b = cmos_read(REG_STATUS_B);
+        let encode = |val: Option<u8>| match val {
+            None => ALARM_ANY,
+            Some(val) if status_b.get_bit(STATUS_B_BINARY) => val,
+            Some(val) => bin_to_bcd(val),
+        };
+        let hour = match hour {
+            Some(hour) if !status_b.get_bit(STATUS_B_24H) => {
+                let mut hour12 = encode(Some(if hour % 12 == 0 { 12 } else { hour % 12 }));
+                hour12.set_bit(HOURS_PM, hour >= 12);
+                hour12
+            }
+            hour => encode(hour),
+        };
+        cmos_write(REG_SECONDS_ALARM, encode(second));
+        cmos_write(REG_MINUTES_ALARM, encode(minute));
+        cmos_write(REG_HOURS_ALARM, hour);
+        unsafe { ALARM_CALLBACK = Some(callback) };
+        status_b.set_bit(STATUS_B_AIE, true);
+        cmos_write(REG_STATUS_B, status_b);
+    })
+}
+
+pub fn clear_alarm() {
+    without_interrupts(|| {
+        let mut status_b = cmos_read(REG_STATUS_B);
+        status_b.set_bit(STATUS_B_AIE, false);
+        cmos_write(REG_STATUS_B, status_b);
+        unsafe { ALARM_CALLBACK = None };
+    })
+}
+
+/// Called by the HPET when its legacy replacement route takes IRQ 8 over, or gives it
+/// back if TAKEN is `false`. The interrupt flags are polled meanwhile: the periodic
+/// interrupt is counted at most once per poll and alarms run up to `POLL_PERIOD_MS` late.
+pub fn set_irq_taken(taken: bool) {
+    without_interrupts(|| unsafe {
+        match (taken, POLL_TIMER) {
+            (true, None) => {
+                let timer = time::add_periodic_timer(POLL_PERIOD_MS, poll_flags, 0);
+                if timer.is_none() {
+                    serial_println!("RTC: no timer left to poll the interrupt flags");
+                }
+                POLL_TIMER = timer;
+            }
+            (false, Some(timer)) => {
+                time::cancel_timer(timer);
+                POLL_TIMER = None;
+            }
+            _ => {}
+        }
+    })
+}
+
+fn poll_flags(_: usize) {
+    handle_flags();
+}
+
+extern "x86-interrupt" fn rtc_handler(_f: ExceptionStackFrame) {
+    let Some(_irq) = irq::irq_enter(RTC_IRQ) else {
+        return;
+    };
+    handle_flags();
+}
+
+/// Acknowledges the pending interrupts and handles them.
+fn handle_flags() {
+    // The RTC raises no further interrupt until status C has been read.
+    let status_c = without_interrupts(|| cmos_read(REG_STATUS_C));
+    if status_c.get_bit(STATUS_C_PF) {
+        PERIODIC_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    }
+    if status_c.get_bit(STATUS_C_AF) {
+        if let Some(callback) = unsafe { ALARM_CALLBACK } {
+            callback();
+        }
+    }
+}
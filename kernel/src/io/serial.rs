@@ -1,9 +1,17 @@
 #![allow(dead_code)]
 
-use core::fmt;
+use core::{fmt, future::poll_fn, task::Poll};
 
 use crate::{
-    utils::singleton::Singleton,
+    arch::x86::{
+        intr::{ExceptionStackFrame, INTR_TABLE},
+        pic::IRQ_VECTOR_BASE,
+        without_interrupts,
+    },
+    irq,
+    sync::WaitQueue,
+    task::WakerSlot,
+    utils::{ring::RingBuffer, singleton::Singleton},
     x86::{inb, outb},
 };
 
@@ -151,6 +159,11 @@ impl SerialPort {
         wait_for!(self.line_sts().0 & INPUT_FULL == INPUT_FULL);
         inb(self.port_data())
     }
+
+    /// Receives a byte on the serial port if one is available.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        (self.line_sts().0 & INPUT_FULL == INPUT_FULL).then(|| inb(self.port_data()))
+    }
 }
 
 impl fmt::Write for SerialPort {
@@ -172,6 +185,64 @@ impl Default for SerialPort {
 
 static SERIAL_IO: Singleton<SerialPort> = Singleton::UNINIT;
 
+/// IRQ line of COM1.
+const SERIAL_IRQ: u8 = 4;
+
+/// Bytes received by the interrupt handler and not read yet.
+static SERIAL_INPUT: Singleton<RingBuffer<u8, 256>> = Singleton::UNINIT;
+
+/// Threads waiting in `read_byte`.
+static SERIAL_READERS: WaitQueue = WaitQueue::new();
+
+/// Task waiting in `read_byte_async`.
+static SERIAL_WAKER: WakerSlot = WakerSlot::new();
+
+/// Installs the receive interrupt handler, so that reads sleep instead of polling.
+pub fn init() {
+    SERIAL_IO.get_mut();
+    INTR_TABLE.get_mut()[(IRQ_VECTOR_BASE + SERIAL_IRQ) as usize].set_handle_fn(serial_handler);
+}
+
+extern "x86-interrupt" fn serial_handler(_f: ExceptionStackFrame) {
+    let Some(_irq) = irq::irq_enter(SERIAL_IRQ) else {
+        return;
+    };
+    // Drains the FIFO, the bytes that do not fit in the buffer are lost.
+    while let Some(byte) = SERIAL_IO.get_mut().try_receive() {
+        without_interrupts(|| SERIAL_INPUT.get_mut().push(byte));
+    }
+    SERIAL_READERS.wake_all();
+    SERIAL_WAKER.wake();
+}
+
+/// Reads a byte received on the serial port, sleeping until one arrives.
+pub fn read_byte() -> u8 {
+    let mut byte = None;
+    SERIAL_READERS.wait_event(|| {
+        byte = SERIAL_INPUT.get_mut().pop();
+        byte.is_some()
+    });
+    byte.unwrap()
+}
+
+/// Reads a byte received on the serial port from a task, there must be only one such
+/// reader at a time.
+pub async fn read_byte_async() -> u8 {
+    poll_fn(|cx| {
+        SERIAL_WAKER.register(cx.waker());
+        match try_read_byte() {
+            Some(byte) => Poll::Ready(byte),
+            None => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Reads a byte received on the serial port if there is one.
+pub fn try_read_byte() -> Option<u8> {
+    without_interrupts(|| SERIAL_INPUT.get_mut().pop())
+}
+
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => ($crate::io::serial::_serial_print(format_args!($($arg)*)));
@@ -183,8 +254,22 @@ macro_rules! serial_println {
     ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
 }
 
+/// Like `serial_println!`, prefixed with the monotonic time in seconds.
+#[macro_export]
+macro_rules! serial_log {
+    ($($arg:tt)*) => ({
+        let ns = $crate::time::now_ns();
+        $crate::serial_println!(
+            "[{:>5}.{:06}] {}",
+            ns / 1_000_000_000,
+            ns / 1000 % 1_000_000,
+            format_args!($($arg)*)
+        )
+    });
+}
+
 #[doc(hidden)]
 pub fn _serial_print(args: fmt::Arguments) {
     use core::fmt::Write;
-    SERIAL_IO.get_mut().write_fmt(args).unwrap();
+    without_interrupts(|| SERIAL_IO.get_mut().write_fmt(args).unwrap());
 }
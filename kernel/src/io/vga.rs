@@ -7,7 +7,7 @@ use core::{
 
 use crate::{
     utils::singleton::Singleton,
-    x86::{inb, outb},
+    x86::{inb, outb, without_interrupts},
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -213,7 +213,7 @@ static VGA_BUFFER: Singleton<VgaBuffer> = Singleton::UNINIT;
 #[doc(hidden)]
 pub fn _vga_print(args: fmt::Arguments) {
     use core::fmt::Write;
-    VGA_BUFFER.get_mut().write_fmt(args).unwrap();
+    without_interrupts(|| VGA_BUFFER.get_mut().write_fmt(args).unwrap());
 }
 
 #[macro_export]
@@ -0,0 +1,98 @@
+#![allow(dead_code)]
+
+use core::sync::atomic::Ordering;
+
+use crate::{
+    arch::x86::{self, pic::pic_set_level_mask},
+    smp,
+};
+
+use super::work;
+
+/// Interrupt request level.
+///
+/// Code running at some level can only be interrupted by the PIC lines of a higher
+/// level, all the lines at or below it are masked. The PIC lines keep their hardware
+/// priority: IRQ 0 is the highest, then IRQ 1, the slave lines 8...15, and finally
+/// lines 3...7.
+///
+/// Each CPU has its own level, but only the bootstrap processor receives the PIC lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct Irql(u8);
+
+impl Irql {
+    /// Normal execution, every line is enabled.
+    pub const PASSIVE: Irql = Irql(0);
+
+    /// Deferred work: every line is enabled, but deferred work does not nest.
+    pub const DISPATCH: Irql = Irql(1);
+
+    /// Every maskable interrupt is disabled.
+    pub const HIGH: Irql = Irql(31);
+
+    /// The level at which the handler of PIC line IRQ runs.
+    pub fn of_irq(irq: u8) -> Irql {
+        let rank = IRQ_PRIORITY
+            .iter()
+            .position(|&line| line == irq)
+            .expect("no level for the cascade line");
+        Irql(DEVICE_LEVEL_MAX - rank as u8)
+    }
+}
+
+/// PIC lines from the highest priority to the lowest, line 2 is the slave cascade.
+const IRQ_PRIORITY: [u8; 15] = [0, 1, 8, 9, 10, 11, 12, 13, 14, 15, 3, 4, 5, 6, 7];
+
+const DEVICE_LEVEL_MAX: u8 = Irql::DISPATCH.0 + IRQ_PRIORITY.len() as u8;
+
+pub fn current_level() -> Irql {
+    Irql(smp::this_cpu().level.load(Ordering::Relaxed))
+}
+
+fn level_mask(level: Irql) -> u16 {
+    IRQ_PRIORITY
+        .iter()
+        .filter(|&&irq| Irql::of_irq(irq) <= level)
+        .fold(0, |mask, &irq| mask | 1 << irq)
+}
+
+/// Switches to LEVEL without touching `EFLAGS.IF`, interrupts must be disabled.
+pub(super) fn set_level(level: Irql) {
+    let old = smp::this_cpu().level.swap(level.0, Ordering::Relaxed);
+    if old != level.0 && smp::is_bsp() {
+        pic_set_level_mask(level_mask(level));
+    }
+}
+
+/// Raises the interrupt level to LEVEL and returns the previous one.
+///
+/// Panics if LEVEL is below the current level.
+pub fn raise_level(level: Irql) -> Irql {
+    x86::cli();
+    let old = current_level();
+    assert!(level >= old, "raise to {:?} from {:?}", level, old);
+    set_level(level);
+    if level < Irql::HIGH {
+        x86::sti();
+    }
+    old
+}
+
+/// Lowers the interrupt level back to LEVEL, as returned by `raise_level`.
+///
+/// Leaving `Irql::DISPATCH` runs the deferred work queued in the meantime.
+///
+/// Panics if LEVEL is above the current level.
+pub fn lower_level(level: Irql) {
+    x86::cli();
+    let old = current_level();
+    assert!(level <= old, "lower to {:?} from {:?}", level, old);
+    set_level(level);
+    if level < Irql::DISPATCH && old >= Irql::DISPATCH && !super::in_interrupt() {
+        work::run_pending();
+    }
+    if level < Irql::HIGH {
+        x86::sti();
+    }
+}
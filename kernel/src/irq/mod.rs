@@ -0,0 +1,129 @@
+#![allow(dead_code)]
+
+use core::sync::atomic::Ordering;
+
+use crate::arch::x86::{
+    self,
+    apic::lapic_end_of_interrupt,
+    intr::{ExceptionStackFrame, INTR_TABLE},
+    pic::{pic_end_of_interrupt, pic_is_spurious, IRQ_VECTOR_BASE},
+};
+use crate::{
+    smp::{self, klock},
+    thread, time,
+};
+
+use level::Irql;
+
+pub mod level;
+pub mod stat;
+pub mod work;
+
+/// Returns whether the caller runs inside a hardware interrupt handler.
+pub fn in_interrupt() -> bool {
+    smp::this_cpu().irq_depth.load(Ordering::Relaxed) != 0
+}
+
+/// Handler context of an interrupt, created by `irq_enter` or `local_irq_enter`.
+///
+/// Dropping it accounts the time spent in the handler, sends the EOI, restores the
+/// interrupted level and, when that level is below `Irql::DISPATCH`, runs the deferred
+/// work with interrupts enabled and lets the scheduler preempt the interrupted thread.
+/// The kernel lock is released last, the interrupted code did not hold it.
+pub struct IrqGuard {
+    /// The PIC line, `None` for an interrupt of the local APIC.
+    irq: Option<u8>,
+    start: u64,
+    old_level: Irql,
+}
+
+/// Enters the handler of PIC line IRQ (the top half).
+///
+/// The handler runs at the level of the line with interrupts enabled, so only the lines
+/// of higher priority can preempt it.
+///
+/// Returns `None` for a spurious interrupt, which the handler must ignore.
+pub fn irq_enter(irq: u8) -> Option<IrqGuard> {
+    if pic_is_spurious(irq) {
+        stat::account_spurious(irq);
+        return None;
+    }
+    smp::this_cpu().irq_depth.fetch_add(1, Ordering::Relaxed);
+    stat::account_enter(irq);
+    let start = time::now_ns();
+    let old_level = level::raise_level(Irql::of_irq(irq));
+    Some(IrqGuard {
+        irq: Some(irq),
+        start,
+        old_level,
+    })
+}
+
+/// Enters the handler of an interrupt raised by the local APIC.
+///
+/// Such interrupts (the local timer, inter-processor interrupts) are not PIC lines, so
+/// the handler keeps running with interrupts disabled.
+pub fn local_irq_enter() -> IrqGuard {
+    smp::this_cpu().irq_depth.fetch_add(1, Ordering::Relaxed);
+    IrqGuard {
+        irq: None,
+        start: 0,
+        old_level: level::current_level(),
+    }
+}
+
+impl Drop for IrqGuard {
+    fn drop(&mut self) {
+        x86::cli();
+        match self.irq {
+            Some(irq) => {
+                stat::account_exit(irq, time::now_ns() - self.start);
+                pic_end_of_interrupt(irq);
+            }
+            None => lapic_end_of_interrupt(),
+        }
+        level::set_level(self.old_level);
+        smp::this_cpu().irq_depth.fetch_sub(1, Ordering::Relaxed);
+        if self.old_level < Irql::DISPATCH {
+            work::run_pending();
+            thread::preempt();
+        }
+        klock::release();
+    }
+}
+
+/// Context of an exception handler, created by `exception_enter`.
+///
+/// Exceptions may be raised with the kernel lock held, or not. Dropping the guard
+/// releases the lock if the handler took it.
+pub struct ExceptionGuard {
+    held: bool,
+}
+
+pub fn exception_enter() -> ExceptionGuard {
+    ExceptionGuard {
+        held: klock::held(),
+    }
+}
+
+impl Drop for ExceptionGuard {
+    fn drop(&mut self) {
+        if !self.held {
+            klock::release();
+        }
+    }
+}
+
+/// Installs the handlers of the lines that can receive spurious interrupts.
+pub fn init() {
+    INTR_TABLE.get_mut()[(IRQ_VECTOR_BASE + 7) as usize].set_handle_fn(irq7_handler);
+    INTR_TABLE.get_mut()[(IRQ_VECTOR_BASE + 15) as usize].set_handle_fn(irq15_handler);
+}
+
+extern "x86-interrupt" fn irq7_handler(_f: ExceptionStackFrame) {
+    let Some(_irq) = irq_enter(7) else { return };
+}
+
+extern "x86-interrupt" fn irq15_handler(_f: ExceptionStackFrame) {
+    let Some(_irq) = irq_enter(15) else { return };
+}
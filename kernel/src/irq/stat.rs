@@ -0,0 +1,81 @@
+#![allow(dead_code)]
+
+use crate::{
+    arch::x86::{pic::IRQ_VECTOR_BASE, without_interrupts},
+    serial_println, time,
+    utils::singleton::Singleton,
+};
+
+pub const NR_IRQS: usize = 16;
+
+/// Statistics of one PIC interrupt line.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IrqStat {
+    /// Interrupts handled.
+    pub count: u64,
+    /// Spurious interrupts ignored, only possible on IRQ 7 and 15.
+    pub spurious: u64,
+    /// Timer tick at which the last interrupt was handled.
+    pub last_tick: u64,
+    /// Total time spent in the handler, in nanoseconds.
+    pub time_spent: u64,
+    /// Longest single run of the handler, in nanoseconds.
+    pub max_time: u64,
+}
+
+impl IrqStat {
+    pub fn time_spent_us(&self) -> u64 {
+        self.time_spent / 1000
+    }
+
+    pub fn max_time_us(&self) -> u64 {
+        self.max_time / 1000
+    }
+}
+
+#[derive(Default)]
+struct IrqStats([IrqStat; NR_IRQS]);
+
+static IRQ_STATS: Singleton<IrqStats> = Singleton::UNINIT;
+
+pub(super) fn account_spurious(irq: u8) {
+    IRQ_STATS.get_mut().0[irq as usize].spurious += 1;
+}
+
+pub(super) fn account_enter(irq: u8) {
+    let stat = &mut IRQ_STATS.get_mut().0[irq as usize];
+    stat.count += 1;
+    stat.last_tick = time::ticks();
+}
+
+pub(super) fn account_exit(irq: u8, elapsed_ns: u64) {
+    let stat = &mut IRQ_STATS.get_mut().0[irq as usize];
+    stat.time_spent += elapsed_ns;
+    stat.max_time = stat.max_time.max(elapsed_ns);
+}
+
+/// Returns a snapshot of the statistics of line IRQ.
+pub fn irq_stat(irq: u8) -> IrqStat {
+    without_interrupts(|| IRQ_STATS.0[irq as usize])
+}
+
+/// Prints the statistics of every line that has seen an interrupt on the serial port.
+pub fn dump() {
+    serial_println!("IRQ VEC       COUNT  SPURIOUS   LAST TICK   TIME(us)    MAX(us)");
+    for irq in 0..NR_IRQS as u8 {
+        let stat = irq_stat(irq);
+        if stat.count == 0 && stat.spurious == 0 {
+            continue;
+        }
+        serial_println!(
+            "{:>3} {:#04x} {:>10} {:>9} {:>11} {:>10} {:>10}",
+            irq,
+            IRQ_VECTOR_BASE + irq,
+            stat.count,
+            stat.spurious,
+            stat.last_tick,
+            stat.time_spent_us(),
+            stat.max_time_us()
+        );
+    }
+}
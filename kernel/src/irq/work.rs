@@ -0,0 +1,97 @@
+#![allow(dead_code)]
+
+use crate::{
+    arch::x86::{self, without_interrupts},
+    utils::{ring::RingBuffer, singleton::Singleton},
+};
+
+use super::level::{self, Irql};
+
+/// Deferred work (bottom halves).
+///
+/// Top halves run with interrupts disabled and should only touch the hardware, anything
+/// slow is queued with `schedule_work` and executed by `run_pending` once the interrupt
+/// has been acknowledged, with interrupts enabled again.
+
+const WORK_QUEUE_SIZE: usize = 64;
+
+#[derive(Clone, Copy)]
+pub struct Work {
+    func: fn(usize),
+    arg: usize,
+}
+
+impl Work {
+    pub fn new(func: fn(usize), arg: usize) -> Self {
+        Self { func, arg }
+    }
+
+    pub fn run(self) {
+        (self.func)(self.arg)
+    }
+}
+
+#[derive(Default)]
+pub struct WorkQueue {
+    items: RingBuffer<Work, WORK_QUEUE_SIZE>,
+    dropped: u32,
+}
+
+impl WorkQueue {
+    pub fn push(&mut self, work: Work) -> bool {
+        if !self.items.push(work) {
+            self.dropped += 1;
+            return false;
+        }
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<Work> {
+        self.items.pop()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Number of work items lost because the queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+static WORK_QUEUE: Singleton<WorkQueue> = Singleton::UNINIT;
+
+/// Queues `func(arg)` to run after the current interrupt has been handled.
+///
+/// Safe to call from interrupt handlers. Returns `false` if the queue is full and the
+/// work was dropped.
+pub fn schedule_work(func: fn(usize), arg: usize) -> bool {
+    without_interrupts(|| WORK_QUEUE.get_mut().push(Work::new(func, arg)))
+}
+
+/// Runs all queued work at `Irql::DISPATCH`, with interrupts enabled.
+///
+/// Does nothing when called at or above `Irql::DISPATCH`, the work is then run when the
+/// level is lowered. Returns with interrupts in the same state as on entry.
+pub fn run_pending() {
+    let enabled = x86::intr_enabled();
+    x86::cli();
+    let old = level::current_level();
+    if old < Irql::DISPATCH {
+        level::set_level(Irql::DISPATCH);
+        while let Some(work) = WORK_QUEUE.get_mut().pop() {
+            x86::sti();
+            work.run();
+            x86::cli();
+        }
+        level::set_level(old);
+    }
+    if enabled {
+        x86::sti();
+    }
+}
+
+pub fn dropped_work() -> u32 {
+    without_interrupts(|| WORK_QUEUE.get_mut().dropped())
+}
@@ -19,5 +19,8 @@ pub fn get_memlayout() -> &'static [MemLayout] {
 pub const KERNEL_VADDR_BASE: u32 = 0xc0000000;
 pub const KERNEL_STACK_PADDR: u32 = 0x7c00;
 pub const KERNEL_PLACE_BEGIN_PADDR: u32 = 0x20000;
+pub const KERNEL_PAGE_DIR_PADDR: u32 = 0xf000;
+/// The loader maps the low 64 MiB both at 0 and at `KERNEL_VADDR_BASE`.
+pub const KERNEL_DIRECT_MAP_SIZE: u32 = 64 * 1024 * 1024;
 
 pub const SEGMENT_KERNEL_CODE: u16 = 0x8;
@@ -4,24 +4,33 @@
 
 extern crate alloc;
 
-use core::arch::asm;
-
+mod acpi;
 mod arch;
 mod io;
+mod irq;
 mod loader;
 mod mm;
+mod smp;
+mod sync;
+mod task;
 mod thread;
+mod time;
+mod user;
 mod utils;
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::{
+    arch::global_asm,
+    ptr::{addr_of, copy_nonoverlapping},
+    slice,
+};
 use arch::x86::{
-    self, inb,
-    intr::{end_of_interrupt, ExceptionStackFrame, INTR_TABLE},
-    pic::{pic_init, pit_configure_channel},
+    self,
+    intr::{ExceptionStackFrame, INTR_TABLE},
+    pic::pic_init,
 };
-
-static mut TICKS: u64 = 0;
-const TIMER_FREQ: u32 = 200;
+use mm::page::{self, PAGE_ALLOC, PAGE_SIZE, PG_RW, PG_USER};
+use user::UserFault;
 
 #[export_name = "_start"]
 fn main() -> ! {
@@ -34,8 +43,11 @@ fn main() -> ! {
         mm::available_mem_size() / 1024
     );
 
+    x86::gdt::init();
     pic_init();
-    pit_configure_channel(0, 2, TIMER_FREQ);
+    time::init();
+    thread::init(thread::SchedMode::Priority);
+    sync::rcu::init();
 
     INTR_TABLE
         .get_mut()
@@ -53,14 +65,16 @@ fn main() -> ! {
         .get_mut()
         .segment_not_present
         .set_handle_fn(segment_not_present_handler);
-    INTR_TABLE.get_mut()[0x20].set_handle_fn(timer_handler);
-    INTR_TABLE.get_mut()[0x21].set_handle_fn(keyboard_handler);
+    user::init();
+    io::keyboard::init();
+    io::serial::init();
+    irq::init();
 
     INTR_TABLE.get_mut().update();
 
-    unsafe {
-        asm!("sti");
-    }
+    x86::sti();
+    smp::init();
+    user::process::init();
 
     for m in loader::get_memlayout() {
         println!("{:?}", m);
@@ -72,14 +86,200 @@ fn main() -> ! {
     let a = Box::new(42);
     println!("{} at {:p}", a, a);
 
+    let worker = thread::spawn(|n| n * 2, 21);
+    println!("thread {} returned {}", worker.tid(), worker.join());
+
+    task::spawn(shell());
+    thread::spawn(|_| task::run(), 0);
+
+    thread::exit(0)
+}
+
+/// Reads the lines typed on the keyboard, which echoes them, and runs them.
+async fn shell() {
+    let mut line = String::new();
     loop {
-        unsafe {
-            asm!("hlt");
+        match io::keyboard::read_char_async().await {
+            '\n' => {
+                match line.as_str() {
+                    "" => {}
+                    "uptime" => println!("{:?}", time::uptime()),
+                    "sleep" => task::sleep(1000).await,
+                    "ring3" => {
+                        thread::spawn(ring3_demo, 0);
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("exec") => {
+                        let mut argv: Vec<String> = vec!["hello".into()];
+                        argv.extend(cmd.split_whitespace().skip(1).map(String::from));
+                        match user::process::spawn(hello_elf(), &argv, &["TERM=vga".into()]) {
+                            Ok(pid) => println!("started process {}", pid),
+                            Err(err) => println!("exec: {:?}", err),
+                        }
+                    }
+                    cmd => println!("unknown command: {}", cmd),
+                }
+                line.clear();
+            }
+            '\x08' => {
+                line.pop();
+            }
+            ch => line.push(ch),
         }
     }
 }
 
+/// Where `ring3_demo` maps its code, its stack is the page below.
+const RING3_DEMO_BASE: usize = 0x0800_0000;
+
+// Position independent, copied to `RING3_DEMO_BASE`.
+global_asm!(
+    ".global ring3_demo_start",
+    ".global ring3_demo_end",
+    "ring3_demo_start:",
+    "    call ring3_demo_pc",
+    "ring3_demo_pc:",
+    "    pop ecx",
+    "    add ecx, offset ring3_demo_msg_offset",
+    "    mov eax, {write}",
+    "    mov ebx, 1",
+    "    mov edx, offset ring3_demo_msg_len",
+    "    mov ebp, {vdso}",
+    "    call ebp",
+    "    ud2",
+    "ring3_demo_msg:",
+    "    .ascii \"hello from ring 3\\n\"",
+    "ring3_demo_end:",
+    ".set ring3_demo_msg_offset, ring3_demo_msg - ring3_demo_pc",
+    ".set ring3_demo_msg_len, ring3_demo_end - ring3_demo_msg",
+    write = const user::syscall::SYS_WRITE,
+    vdso = const user::vdso::VDSO_ADDR,
+);
+
+extern "C" {
+    static ring3_demo_start: u8;
+    static ring3_demo_end: u8;
+}
+
+/// Writes a line with a system call through the vDSO in ring 3, then runs `ud2`, which
+/// kills the thread with an invalid opcode fault.
+fn ring3_demo(_: usize) -> usize {
+    for vaddr in [RING3_DEMO_BASE - PAGE_SIZE, RING3_DEMO_BASE] {
+        x86::without_interrupts(|| {
+            let frame = PAGE_ALLOC.get_mut().get_page(1).expect("out of memory");
+            if let Some(old) = page::unmap_page(page::kernel_page_dir(), vaddr) {
+                PAGE_ALLOC.get_mut().free_page(mm::ptov(old) as *mut u8, 1);
+            }
+            page::map_page(
+                page::kernel_page_dir(),
+                vaddr,
+                mm::vtop(frame as usize),
+                PG_USER | PG_RW,
+            );
+        });
+    }
+    unsafe {
+        let start = addr_of!(ring3_demo_start);
+        let len = addr_of!(ring3_demo_end) as usize - start as usize;
+        copy_nonoverlapping(start, RING3_DEMO_BASE as *mut u8, len);
+    }
+    user::enter_user(RING3_DEMO_BASE, RING3_DEMO_BASE)
+}
+
+/// Where `hello_elf` is linked.
+const HELLO_ELF_BASE: usize = 0x0804_8000;
+
+// A whole ELF executable, with a single segment mapping the file at `HELLO_ELF_BASE`.
+// It writes each of its arguments on a line through the entry given by `AT_SYSINFO`,
+// then exits with their count.
+global_asm!(
+    ".pushsection .rodata.hello_elf, \"a\"",
+    ".balign 4",
+    ".global hello_elf_start",
+    ".global hello_elf_end",
+    "hello_elf_start:",
+    "    .byte 0x7f, 0x45, 0x4c, 0x46, 1, 1, 1, 0",
+    "    .zero 8",
+    "    .short 2, 3",
+    "    .long 1, hello_elf_entry, hello_elf_phdr - hello_elf_start, 0, 0",
+    "    .short hello_elf_phdr - hello_elf_start, hello_elf_code - hello_elf_phdr, 1, 0, 0, 0",
+    "hello_elf_phdr:",
+    "    .long 1, 0, {base}, {base}",
+    "    .long hello_elf_end - hello_elf_start, hello_elf_end - hello_elf_start, 5, 0x1000",
+    "hello_elf_code:",
+    "    mov esi, [esp]",
+    "    lea edi, [esp + 4]",
+    "    lea ebx, [edi + esi * 4 + 4]",
+    "hello_elf_envp:",
+    "    mov eax, [ebx]",
+    "    add ebx, 4",
+    "    test eax, eax",
+    "    jnz hello_elf_envp",
+    "    mov ebp, offset hello_elf_int80",
+    "hello_elf_auxv:",
+    "    mov eax, [ebx]",
+    "    test eax, eax",
+    "    jz hello_elf_print",
+    "    add ebx, 8",
+    "    cmp eax, {at_sysinfo}",
+    "    jne hello_elf_auxv",
+    "    mov ebp, [ebx - 4]",
+    "hello_elf_print:",
+    "    mov ecx, [edi]",
+    "    test ecx, ecx",
+    "    jz hello_elf_exit",
+    "    mov edx, ecx",
+    "hello_elf_strlen:",
+    "    cmp byte ptr [edx], 0",
+    "    je hello_elf_write",
+    "    inc edx",
+    "    jmp hello_elf_strlen",
+    "hello_elf_write:",
+    "    sub edx, ecx",
+    "    mov eax, {write}",
+    "    mov ebx, 1",
+    "    call ebp",
+    "    mov eax, {write}",
+    "    mov ebx, 1",
+    "    mov ecx, offset hello_elf_newline",
+    "    mov edx, 1",
+    "    call ebp",
+    "    add edi, 4",
+    "    jmp hello_elf_print",
+    "hello_elf_exit:",
+    "    mov eax, {exit}",
+    "    mov ebx, esi",
+    "    call ebp",
+    "hello_elf_int80_code:",
+    "    int {vector}",
+    "    ret",
+    "hello_elf_newline_byte:",
+    "    .byte 10",
+    "hello_elf_end:",
+    ".set hello_elf_entry, {base} + hello_elf_code - hello_elf_start",
+    ".set hello_elf_int80, {base} + hello_elf_int80_code - hello_elf_start",
+    ".set hello_elf_newline, {base} + hello_elf_newline_byte - hello_elf_start",
+    ".popsection",
+    base = const HELLO_ELF_BASE,
+    at_sysinfo = const 32,
+    write = const user::syscall::SYS_WRITE,
+    exit = const user::syscall::SYS_EXIT,
+    vector = const user::syscall::SYSCALL_VECTOR,
+);
+
+extern "C" {
+    static hello_elf_start: u8;
+    static hello_elf_end: u8;
+}
+
+fn hello_elf() -> &'static [u8] {
+    unsafe {
+        let start = addr_of!(hello_elf_start);
+        slice::from_raw_parts(start, addr_of!(hello_elf_end) as usize - start as usize)
+    }
+}
+
 extern "x86-interrupt" fn breakpoint_handler(f: ExceptionStackFrame) {
+    let _exception = irq::exception_enter();
     println!("BREAKPOINT: {:?}", f);
 }
 
@@ -89,26 +289,20 @@ extern "x86-interrupt" fn double_fault_handler(f: ExceptionStackFrame, _error_co
 }
 
 extern "x86-interrupt" fn page_fault_handler(f: ExceptionStackFrame, error_code: u32) {
-    println!("PAGE FAULT#{} {:?}", error_code, f);
-}
-
-extern "x86-interrupt" fn timer_handler(_f: ExceptionStackFrame) {
-    unsafe {
-        TICKS += 1;
+    if f.is_user_mode() {
+        let addr = x86::cr2();
+        user::fault(UserFault::PageFault { addr, error_code }, &f);
     }
-    end_of_interrupt();
+    let _exception = irq::exception_enter();
+    println!("PAGE FAULT#{} {:?}", error_code, f);
 }
 
 extern "x86-interrupt" fn segment_not_present_handler(f: ExceptionStackFrame, error_code: u32) {
-    println!("SEGMENT NOT PRESENT {} {:?}", error_code, f)
-}
-
-extern "x86-interrupt" fn keyboard_handler(_f: ExceptionStackFrame) {
-    let scancode = inb(0x60);
-    if let Some(ch) = scancode_to_char(scancode) {
-        print!("{}", ch);
+    if f.is_user_mode() {
+        user::fault(UserFault::SegmentNotPresent { error_code }, &f);
     }
-    end_of_interrupt();
+    let _exception = irq::exception_enter();
+    println!("SEGMENT NOT PRESENT {} {:?}", error_code, f)
 }
 
 #[panic_handler]
@@ -117,24 +311,3 @@ pub fn panic(info: &::core::panic::PanicInfo) -> ! {
     serial_println!("{:?}", info);
     loop {}
 }
-
-pub fn scancode_to_char(code: u8) -> Option<char> {
-    // println!("scancode {}", code);
-    match code {
-        0x00 => {
-            panic!("Error Scancode 0x00")
-        }
-        0x02..=0x0a => Some((b'0' + code - 1) as char),
-        0x0b => Some('0'),
-        0x10..=0x19 => {
-            Some(['q', 'w', 'e', 'r', 't', 'y', 'u', 'i', 'o', 'p'][code as usize - 0x10])
-        }
-        0x1c => Some('\n'),
-        0x0e => Some(0x08 as char),
-        0x1e..=0x26 => Some(['a', 's', 'd', 'f', 'g', 'h', 'j', 'k', 'l'][code as usize - 0x1e]),
-        0x2c..=0x32 => Some(['z', 'x', 'c', 'v', 'b', 'n', 'm'][code as usize - 0x2c]),
-        0x39 => Some(' '),
-        0x80.. => None,
-        _ => Some('?'),
-    }
-}
@@ -0,0 +1,159 @@
+#![allow(dead_code)]
+
+use crate::{
+    arch::x86,
+    loader::{KERNEL_PAGE_DIR_PADDR, KERNEL_VADDR_BASE},
+    smp,
+};
+
+use super::{
+    page::{
+        self, PageTable, PageTableEntry, PAGE_ALLOC, PAGE_SIZE, PG_PRESENT, PG_RW, PG_SHARED,
+        PG_USER,
+    },
+    ptov, vtop,
+};
+
+/// Address spaces of user programs.
+///
+/// Each address space has its own page directory. The user part, below
+/// `KERNEL_VADDR_BASE`, is private, and the frames mapped there belong to the address
+/// space unless mapped with `PG_SHARED`. The kernel part shares the page tables of the
+/// kernel page directory, so the kernel mappings made at boot are seen by every address
+/// space. Threads without an address space run on the kernel page directory.
+
+/// First page directory entry of the kernel part.
+const KERNEL_PDE_START: usize = KERNEL_VADDR_BASE as usize >> 22;
+
+pub struct AddressSpace {
+    /// Kernel virtual address of the page directory.
+    dir: *mut PageTable,
+}
+
+unsafe impl Send for AddressSpace {}
+unsafe impl Sync for AddressSpace {}
+
+impl AddressSpace {
+    /// Creates an address space with an empty user part. Returns `None` when out of
+    /// memory.
+    pub fn new() -> Option<Self> {
+        let dir = x86::without_interrupts(|| PAGE_ALLOC.get_mut().get_page(1))? as *mut PageTable;
+        let dir = unsafe { &mut *dir };
+        dir.fill(PageTableEntry::EMPTY);
+        dir[KERNEL_PDE_START..].copy_from_slice(&page::kernel_page_dir()[KERNEL_PDE_START..]);
+        Some(Self { dir })
+    }
+
+    /// Physical address of the page directory, as loaded in CR3.
+    pub fn dir_paddr(&self) -> u32 {
+        vtop(self.dir as usize) as u32
+    }
+
+    fn dir(&mut self) -> &mut PageTable {
+        unsafe { &mut *self.dir }
+    }
+
+    /// The entry of the user page at VADDR, allocating its page table if ALLOC.
+    fn entry(&mut self, vaddr: usize, alloc: bool) -> Option<&mut PageTableEntry> {
+        assert!(
+            vaddr < KERNEL_VADDR_BASE as usize,
+            "kernel address in user mapping"
+        );
+        let pde = &mut self.dir()[vaddr >> 22];
+        if !pde.present() {
+            if !alloc {
+                return None;
+            }
+            let table = x86::without_interrupts(|| PAGE_ALLOC.get_mut().get_page(1))?;
+            unsafe { table.write_bytes(0, PAGE_SIZE) };
+            *pde = PageTableEntry::new(vtop(table as usize) as u32, PG_PRESENT | PG_RW | PG_USER);
+        }
+        let table = unsafe { &mut *(ptov(pde.addr() as usize) as *mut PageTable) };
+        Some(&mut table[(vaddr >> 12) & 0x3ff])
+    }
+
+    /// Maps a zeroed frame of its own at the page VADDR with the `PG_*` bits FLAGS, or
+    /// adds FLAGS to the page already mapped there. Returns the kernel address of the
+    /// frame, `None` when out of memory.
+    pub fn map_new(&mut self, vaddr: usize, flags: u32) -> Option<*mut u8> {
+        assert!(vaddr.is_multiple_of(PAGE_SIZE));
+        let pte = self.entry(vaddr, true)?;
+        if pte.present() {
+            *pte = PageTableEntry::new(pte.addr(), pte.flags() | flags);
+            // Pages not present are not cached by the TLB, changed ones may be.
+            smp::ipi::flush_tlb(vaddr);
+        } else {
+            let frame = x86::without_interrupts(|| PAGE_ALLOC.get_mut().get_page(1))?;
+            unsafe { frame.write_bytes(0, PAGE_SIZE) };
+            *pte = PageTableEntry::new(vtop(frame as usize) as u32, flags | PG_PRESENT);
+        }
+        Some(ptov(pte.addr() as usize) as *mut u8)
+    }
+
+    /// Maps the frame at PADDR, which the address space does not own, at the page VADDR
+    /// with the `PG_*` bits FLAGS. Returns false when out of memory.
+    pub fn map_shared(&mut self, vaddr: usize, paddr: usize, flags: u32) -> bool {
+        assert!(vaddr.is_multiple_of(PAGE_SIZE) && paddr.is_multiple_of(PAGE_SIZE));
+        let Some(pte) = self.entry(vaddr, true) else {
+            return false;
+        };
+        assert!(!pte.present(), "page {:#x} already mapped", vaddr);
+        *pte = PageTableEntry::new(paddr as u32, flags | PG_SHARED | PG_PRESENT);
+        true
+    }
+
+    /// Copies BYTES to user address VADDR, whatever the permissions of the pages.
+    /// Returns false if a page is not mapped.
+    pub fn write(&mut self, vaddr: usize, bytes: &[u8]) -> bool {
+        let mut done = 0;
+        while done < bytes.len() {
+            let addr = vaddr + done;
+            let Some(pte) = self.entry(addr & !(PAGE_SIZE - 1), false) else {
+                return false;
+            };
+            if !pte.present() {
+                return false;
+            }
+            let offset = addr % PAGE_SIZE;
+            let len = (PAGE_SIZE - offset).min(bytes.len() - done);
+            unsafe {
+                let dst = (ptov(pte.addr() as usize) + offset) as *mut u8;
+                dst.copy_from_nonoverlapping(bytes[done..].as_ptr(), len);
+            }
+            done += len;
+        }
+        true
+    }
+
+    /// Loads the page directory of SPACE on the running CPU, or the kernel one for
+    /// `None`. Interrupts must be disabled.
+    pub fn activate(space: Option<&AddressSpace>) {
+        let dir = space.map_or(KERNEL_PAGE_DIR_PADDR, |space| space.dir_paddr());
+        if x86::cr3() != dir {
+            x86::set_cr3(dir);
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees the frames and page tables of the user part. The address space must not be
+    /// loaded on any CPU.
+    fn drop(&mut self) {
+        x86::without_interrupts(|| {
+            let alloc = PAGE_ALLOC.get_mut();
+            for pde in self.dir()[..KERNEL_PDE_START]
+                .iter()
+                .filter(|pde| pde.present())
+            {
+                let table = ptov(pde.addr() as usize) as *mut PageTable;
+                for pte in unsafe { &*table }.iter() {
+                    if pte.present() && pte.flags() & PG_SHARED == 0 {
+                        alloc.free_page(ptov(pte.addr() as usize) as *mut u8, 1);
+                    }
+                }
+                alloc.free_page(table as *mut u8, 1);
+            }
+            alloc.free_page(self.dir as *mut u8, 1);
+        });
+    }
+}
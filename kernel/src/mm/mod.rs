@@ -4,8 +4,10 @@ use core::{alloc::GlobalAlloc, fmt::Debug, ptr::addr_of};
 
 use page::PAGE_ALLOC;
 
-use crate::loader;
+use crate::arch::x86::without_interrupts;
+use crate::loader::{self, KERNEL_DIRECT_MAP_SIZE, KERNEL_VADDR_BASE};
 
+pub mod addr_space;
 pub mod page;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -71,20 +73,67 @@ pub fn pg_round_down(addr: usize) -> usize {
     addr & (!((1 << 12) - 1))
 }
 
+/// Kernel virtual address of physical address PADDR, which must be in the direct map.
+pub fn ptov(paddr: usize) -> usize {
+    assert!(paddr < KERNEL_DIRECT_MAP_SIZE as usize);
+    paddr + KERNEL_VADDR_BASE as usize
+}
+
+/// Physical address of kernel virtual address VADDR, which must be in the direct map.
+pub fn vtop(vaddr: usize) -> usize {
+    assert!(vaddr >= KERNEL_VADDR_BASE as usize);
+    assert!(vaddr - (KERNEL_VADDR_BASE as usize) < KERNEL_DIRECT_MAP_SIZE as usize);
+    vaddr - KERNEL_VADDR_BASE as usize
+}
+
+/// Virtual window for device memory, above the direct map of the kernel.
+const MMIO_BASE: usize = 0xf000_0000;
+const MMIO_END: usize = 0xff00_0000;
+
+static mut MMIO_NEXT: usize = MMIO_BASE;
+
+/// Maps SIZE bytes of device memory at physical address PADDR uncached into the
+/// kernel address space, returning the virtual address of PADDR.
+///
+/// Mappings are permanent. They are made in the kernel page directory, so they must be
+/// made at boot, before other page directories copy its kernel part.
+pub fn map_mmio(paddr: usize, size: usize) -> usize {
+    let offset = paddr % page::PAGE_SIZE;
+    let first = paddr - offset;
+    let pages = (offset + size).div_ceil(page::PAGE_SIZE);
+    let vaddr = unsafe {
+        let vaddr = MMIO_NEXT;
+        assert!(vaddr + pages * page::PAGE_SIZE <= MMIO_END, "MMIO window exhausted");
+        MMIO_NEXT += pages * page::PAGE_SIZE;
+        vaddr
+    };
+    for i in 0..pages {
+        page::map_page(
+            page::kernel_page_dir(),
+            vaddr + i * page::PAGE_SIZE,
+            first + i * page::PAGE_SIZE,
+            page::PG_RW | page::PG_WRITE_THROUGH | page::PG_CACHE_DISABLE,
+        );
+    }
+    vaddr + offset
+}
+
 pub struct Allocator {}
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         let reqsize = layout.size();
-        PAGE_ALLOC
-            .get_mut()
-            .get_page((reqsize + 4095) / 4096)
-            .unwrap()
+        without_interrupts(|| {
+            PAGE_ALLOC
+                .get_mut()
+                .get_page((reqsize + 4095) / 4096)
+                .unwrap()
+        })
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
         let reqsize = layout.size();
-        PAGE_ALLOC.get_mut().free_page(ptr, (reqsize + 4095) / 4096)
+        without_interrupts(|| PAGE_ALLOC.get_mut().free_page(ptr, (reqsize + 4095) / 4096))
     }
 }
 
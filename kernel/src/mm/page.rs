@@ -1,8 +1,10 @@
 #![allow(dead_code)]
 
+use crate::{arch::x86, smp};
+use crate::loader::{KERNEL_DIRECT_MAP_SIZE, KERNEL_PAGE_DIR_PADDR, KERNEL_VADDR_BASE};
 use crate::utils::{singleton::Singleton, BitAccess};
 use core::fmt::Debug;
-use super::available_mem_size;
+use super::{available_mem_size, ptov, vtop};
 
 pub static PAGE_ALLOC: Singleton<PageAllocator> = Singleton::UNINIT;
 
@@ -141,10 +143,34 @@ impl BitMap {
     }
 }
 
+pub const PAGE_SIZE: usize = 4096;
+pub const PAGE_TABLE_ENTRIES: usize = 1024;
+
+pub const PG_PRESENT: u32 = 1 << 0;
+pub const PG_RW: u32 = 1 << 1;
+pub const PG_USER: u32 = 1 << 2;
+pub const PG_WRITE_THROUGH: u32 = 1 << 3;
+pub const PG_CACHE_DISABLE: u32 = 1 << 4;
+/// Available to software: the frame does not belong to the address space mapping it.
+pub const PG_SHARED: u32 = 1 << 9;
+
+#[derive(Clone, Copy)]
 #[repr(transparent)]
 pub struct PageTableEntry(u32);
 
+pub type PageTable = [PageTableEntry; PAGE_TABLE_ENTRIES];
+
 impl PageTableEntry {
+    pub const EMPTY: PageTableEntry = PageTableEntry(0);
+
+    pub const fn new(addr: u32, flags: u32) -> Self {
+        Self(addr & !0xfff | flags & 0xfff)
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.0 & 0xfff
+    }
+
     pub fn present(&self) -> bool {
         self.0.get_bit(0)
     }
@@ -190,6 +216,90 @@ impl PageTableEntry {
     }
 }
 
+/// The page directory built by the loader, shared by every kernel thread.
+pub fn kernel_page_dir() -> &'static mut PageTable {
+    unsafe { &mut *(ptov(KERNEL_PAGE_DIR_PADDR as usize) as *mut PageTable) }
+}
+
+/// Takes `PG_USER`, which the loader sets on all its mappings, off the kernel part of the
+/// kernel page directory and off the low identity map, which the APs still boot
+/// through. Ring 3 then only reaches the pages mapped for it. Must run on the bootstrap
+/// processor before the APs are started.
+pub fn protect_kernel() {
+    let (low, kernel) = kernel_page_dir().split_at_mut((KERNEL_VADDR_BASE >> 22) as usize);
+    let identity = &mut low[..(KERNEL_DIRECT_MAP_SIZE >> 22) as usize];
+    for pde in identity.iter_mut().chain(kernel).filter(|pde| pde.present()) {
+        // The identity map and the direct map share their page tables.
+        let table = unsafe { &mut *(ptov(pde.addr() as usize) as *mut PageTable) };
+        for pte in table.iter_mut().filter(|pte| pte.present()) {
+            *pte = PageTableEntry::new(pte.addr(), pte.flags() & !PG_USER);
+        }
+        *pde = PageTableEntry::new(pde.addr(), pde.flags() & !PG_USER);
+    }
+    x86::set_cr3(x86::cr3());
+}
+
+/// The page directory loaded on the running CPU.
+pub fn current_page_dir() -> &'static PageTable {
+    unsafe { &*(ptov(x86::cr3() as usize) as *const PageTable) }
+}
+
+/// Maps the page at VADDR to the frame at PADDR in page directory DIR, allocating the
+/// page table if needed. FLAGS are the `PG_*` bits of the page, `PG_PRESENT` implied.
+pub fn map_page(dir: &mut PageTable, vaddr: usize, paddr: usize, flags: u32) {
+    assert!(vaddr.is_multiple_of(PAGE_SIZE) && paddr.is_multiple_of(PAGE_SIZE));
+    let pde = &mut dir[vaddr >> 22];
+    if !pde.present() {
+        let table = PAGE_ALLOC.get_mut().get_page(1).expect("out of memory for page table");
+        unsafe { table.write_bytes(0, PAGE_SIZE) };
+        // The page directory entry lets ring 3 in only for user pages, the page entries
+        // restrict the access further.
+        *pde = PageTableEntry::new(
+            vtop(table as usize) as u32,
+            PG_PRESENT | PG_RW | flags & PG_USER,
+        );
+    } else if flags & PG_USER != 0 && !pde.is_user() {
+        // The other pages of the table keep their own `PG_USER` bits.
+        *pde = PageTableEntry::new(pde.addr(), pde.flags() | PG_USER);
+    }
+    let table = unsafe { &mut *(ptov(pde.addr() as usize) as *mut PageTable) };
+    table[(vaddr >> 12) & 0x3ff] = PageTableEntry::new(paddr as u32, flags | PG_PRESENT);
+    smp::ipi::flush_tlb(vaddr);
+}
+
+/// Removes the mapping of the page at VADDR, returning the frame it was mapped to.
+pub fn unmap_page(dir: &mut PageTable, vaddr: usize) -> Option<usize> {
+    let pde = dir[vaddr >> 22];
+    if !pde.present() {
+        return None;
+    }
+    let table = unsafe { &mut *(ptov(pde.addr() as usize) as *mut PageTable) };
+    let pte = &mut table[(vaddr >> 12) & 0x3ff];
+    if !pte.present() {
+        return None;
+    }
+    let paddr = pte.addr() as usize;
+    *pte = PageTableEntry::EMPTY;
+    smp::ipi::flush_tlb(vaddr);
+    Some(paddr)
+}
+
+/// The page table entry of the page at VADDR in page directory DIR, if present.
+pub fn page_entry(dir: &PageTable, vaddr: usize) -> Option<PageTableEntry> {
+    let pde = dir[vaddr >> 22];
+    if !pde.present() {
+        return None;
+    }
+    let table = unsafe { &*(ptov(pde.addr() as usize) as *const PageTable) };
+    let pte = table[(vaddr >> 12) & 0x3ff];
+    pte.present().then_some(pte)
+}
+
+/// Physical address VADDR is mapped to in page directory DIR.
+pub fn translate(dir: &PageTable, vaddr: usize) -> Option<usize> {
+    page_entry(dir, vaddr).map(|pte| pte.addr() as usize | vaddr & (PAGE_SIZE - 1))
+}
+
 impl Debug for PageTableEntry {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("PageTableEntry")
@@ -0,0 +1,214 @@
+#![allow(dead_code)]
+
+use core::{
+    arch::global_asm,
+    hint::spin_loop,
+    ptr::{addr_of, copy_nonoverlapping},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    acpi,
+    arch::x86::{
+        self,
+        apic::{lapic_enabled, lapic_id, lapic_init, lapic_send_init, lapic_send_startup},
+        gdt,
+        intr::INTR_TABLE,
+    },
+    mm::ptov,
+    serial_println, thread, time, user,
+};
+
+use super::{percpu, CPU_COUNT, MAX_CPUS};
+
+/// Application processor startup.
+///
+/// An AP wakes up in real mode on INIT-SIPI-SIPI, at the trampoline copied to
+/// `TRAMPOLINE_PADDR`. The trampoline switches to protected mode with a temporary GDT,
+/// enables paging with the kernel page directory, still identity mapping the low
+/// memory, and calls `ap_entry` on the stack prepared by the bootstrap processor. The
+/// APs are started one at a time, each one waited for until it is online.
+
+/// Physical address of the trampoline, page aligned below 1 MiB and unused since boot.
+const TRAMPOLINE_PADDR: usize = 0x1000;
+
+/// How long to wait for an AP to come online after its startup IPIs.
+const AP_ONLINE_TIMEOUT_MS: u64 = 100;
+
+global_asm!(
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_end",
+    ".global ap_trampoline_stack",
+    ".global ap_trampoline_entry",
+    ".code16",
+    "ap_trampoline_start:",
+    "    cli",
+    "    cld",
+    "    xor ax, ax",
+    "    mov ds, ax",
+    "    lgdt [{base} + ap_off_gdtdesc]",
+    "    mov eax, cr0",
+    "    or eax, 1",
+    "    mov cr0, eax",
+    // jmp dword 0x8:ap_trampoline_32
+    "    .byte 0x66, 0xea",
+    "    .long {base} + ap_trampoline_32 - ap_trampoline_start",
+    "    .word 0x8",
+    ".code32",
+    "ap_trampoline_32:",
+    "    mov ax, 0x10",
+    "    mov ds, ax",
+    "    mov es, ax",
+    "    mov fs, ax",
+    "    mov gs, ax",
+    "    mov ss, ax",
+    "    mov eax, {page_dir}",
+    "    mov cr3, eax",
+    // Paging and write protection, FPU monitoring without emulation, as the loader.
+    "    mov eax, cr0",
+    "    or eax, 0x80010002",
+    "    and eax, 0xfffffffb",
+    "    mov cr0, eax",
+    "    mov eax, cr4",
+    "    or eax, 0x600",
+    "    mov cr4, eax",
+    "    mov esp, [{base} + ap_off_stack]",
+    "    mov eax, [{base} + ap_off_entry]",
+    "    call eax",
+    "2:",
+    "    hlt",
+    "    jmp 2b",
+    ".balign 8",
+    "ap_trampoline_gdt:",
+    "    .quad 0",
+    "    .quad 0x00cf9a000000ffff",
+    "    .quad 0x00cf92000000ffff",
+    "ap_trampoline_gdtdesc:",
+    "    .word 23",
+    "    .long {base} + ap_trampoline_gdt - ap_trampoline_start",
+    // Filled in by the bootstrap processor for each AP.
+    "ap_trampoline_stack:",
+    "    .long 0",
+    "ap_trampoline_entry:",
+    "    .long 0",
+    "ap_trampoline_end:",
+    // Offsets in the trampoline, a memory operand takes a single symbol.
+    ".set ap_off_gdtdesc, ap_trampoline_gdtdesc - ap_trampoline_start",
+    ".set ap_off_stack, ap_trampoline_stack - ap_trampoline_start",
+    ".set ap_off_entry, ap_trampoline_entry - ap_trampoline_start",
+    base = const TRAMPOLINE_PADDR,
+    page_dir = const crate::loader::KERNEL_PAGE_DIR_PADDR,
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+}
+
+/// Index of the AP being started.
+static AP_BOOTING: AtomicUsize = AtomicUsize::new(0);
+/// Lowest address of the stack of the AP being started.
+static AP_STACK: AtomicUsize = AtomicUsize::new(0);
+
+/// Copies the trampoline to low memory.
+fn install_trampoline() {
+    unsafe {
+        let start = addr_of!(ap_trampoline_start) as usize;
+        let len = addr_of!(ap_trampoline_end) as usize - start;
+        copy_nonoverlapping(start as *const u8, ptov(TRAMPOLINE_PADDR) as *mut u8, len);
+    }
+}
+
+/// Writes VALUE to the trampoline variable at SYMBOL.
+fn set_trampoline_var(symbol: *const u8, value: u32) {
+    unsafe {
+        let offset = symbol as usize - addr_of!(ap_trampoline_start) as usize;
+        (ptov(TRAMPOLINE_PADDR + offset) as *mut u32).write_volatile(value);
+    }
+}
+
+fn delay_us(us: u64) {
+    let start = time::now_ns();
+    while time::now_ns() - start < us * 1000 {
+        spin_loop();
+    }
+}
+
+/// Starts every enabled AP of the MADT, returns how many came online.
+pub(super) fn start_aps() -> usize {
+    let Some(apic_ids) = acpi::madt::local_apic_ids() else {
+        return 0;
+    };
+    if !lapic_enabled() && !lapic_init() {
+        return 0;
+    }
+    install_trampoline();
+    let bsp = lapic_id();
+    percpu::set_apic_id(0, bsp);
+    let mut started = 0;
+    for apic_id in apic_ids.into_iter().filter(|&id| id != bsp) {
+        let cpu = CPU_COUNT.load(Ordering::Relaxed);
+        if cpu == MAX_CPUS {
+            serial_println!("SMP: more than {} CPUs, ignoring the rest", MAX_CPUS);
+            break;
+        }
+        if start_ap(cpu, apic_id) {
+            started += 1;
+        } else {
+            serial_println!("SMP: CPU with APIC id {} did not start", apic_id);
+        }
+    }
+    started
+}
+
+/// Starts the AP of APIC_ID as CPU, returns whether it came online.
+fn start_ap(cpu: usize, apic_id: u8) -> bool {
+    let kstack = thread::new_kstack();
+    let top = kstack as usize + thread::KERNEL_STACK_SIZE;
+    percpu::set_apic_id(cpu, apic_id);
+    AP_BOOTING.store(cpu, Ordering::Relaxed);
+    AP_STACK.store(kstack as usize, Ordering::Relaxed);
+    set_trampoline_var(addr_of!(ap_trampoline_stack), top as u32);
+    set_trampoline_var(addr_of!(ap_trampoline_entry), ap_entry as *const () as u32);
+    // The AP reads its index and stack once running.
+    CPU_COUNT.store(cpu + 1, Ordering::Release);
+
+    lapic_send_init(apic_id);
+    delay_us(10_000);
+    for _ in 0..2 {
+        lapic_send_startup(apic_id, (TRAMPOLINE_PADDR >> 12) as u8);
+        delay_us(200);
+        if percpu::cpu(cpu).is_online() {
+            return true;
+        }
+    }
+    let start = time::now_ns();
+    while time::now_ns() - start < AP_ONLINE_TIMEOUT_MS * 1_000_000 {
+        if percpu::cpu(cpu).is_online() {
+            return true;
+        }
+        spin_loop();
+    }
+    // The slot is reused for the next AP, the stack is lost.
+    CPU_COUNT.store(cpu, Ordering::Release);
+    false
+}
+
+/// First Rust code of an AP, with paging enabled and interrupts disabled. The boot flow
+/// becomes the idle thread of the CPU.
+extern "C" fn ap_entry() -> ! {
+    let cpu = AP_BOOTING.load(Ordering::Acquire);
+    gdt::init_cpu(cpu);
+    // Interrupts are already disabled, this takes the kernel lock.
+    x86::cli();
+    INTR_TABLE.update();
+    user::init_ap();
+    lapic_init();
+    thread::init_ap(AP_STACK.load(Ordering::Relaxed) as *mut u8);
+    time::clockevent::start_ap_timer();
+    percpu::set_online(cpu);
+    x86::sti();
+    thread::idle_loop()
+}
@@ -0,0 +1,89 @@
+#![allow(dead_code)]
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use crate::{
+    arch::x86::{
+        self,
+        apic::{lapic_end_of_interrupt, lapic_send_ipi, ICR_FIXED},
+        intr::{ExceptionStackFrame, INTR_TABLE},
+        without_interrupts,
+    },
+    irq,
+};
+
+use super::{cpu_id, online_cpus, percpu};
+
+/// Inter-processor interrupts.
+
+/// Asks the target to call the scheduler, a thread of higher priority was queued there.
+pub const IPI_RESCHEDULE_VECTOR: u8 = 0x40;
+/// Asks the target to invalidate the TLB entry of `TLB_ADDR`.
+pub const IPI_TLB_VECTOR: u8 = 0x41;
+
+/// Page of the shootdown in progress.
+static TLB_ADDR: AtomicUsize = AtomicUsize::new(0);
+/// CPUs that still have to invalidate `TLB_ADDR`.
+static TLB_PENDING: AtomicU32 = AtomicU32::new(0);
+
+pub fn init() {
+    INTR_TABLE.get_mut()[IPI_RESCHEDULE_VECTOR as usize].set_handle_fn(reschedule_handler);
+    INTR_TABLE.get_mut()[IPI_TLB_VECTOR as usize].set_handle_fn(tlb_handler);
+}
+
+fn send(cpu: usize, vector: u8) {
+    lapic_send_ipi(percpu::cpu(cpu).apic_id(), ICR_FIXED | vector as u32);
+}
+
+/// Makes CPU reconsider what it runs, after its `need_resched` was set.
+pub fn send_reschedule(cpu: usize) {
+    send(cpu, IPI_RESCHEDULE_VECTOR);
+}
+
+extern "x86-interrupt" fn reschedule_handler(_f: ExceptionStackFrame) {
+    // The switch happens when the guard is dropped.
+    let _irq = irq::local_irq_enter();
+}
+
+/// Invalidates the TLB entry of the page at VADDR on every CPU, waiting until they are
+/// all done. The page tables are shared by all the CPUs.
+pub fn flush_tlb(vaddr: usize) {
+    x86::invlpg(vaddr);
+    let me = cpu_id();
+    let others = online_cpus()
+        .filter(|&cpu| cpu != me)
+        .fold(0, |mask, cpu| mask | 1 << cpu);
+    if others == 0 {
+        return;
+    }
+    // The kernel lock orders the shootdowns, the targets handle them even while they
+    // wait for it.
+    without_interrupts(|| {
+        TLB_ADDR.store(vaddr, Ordering::Relaxed);
+        TLB_PENDING.store(others, Ordering::Release);
+        for cpu in online_cpus().filter(|&cpu| cpu != me) {
+            send(cpu, IPI_TLB_VECTOR);
+        }
+        while TLB_PENDING.load(Ordering::Acquire) != 0 {
+            spin_loop();
+        }
+    })
+}
+
+/// Invalidates the page of the shootdown in progress if the running CPU has not yet.
+pub(super) fn handle_tlb_shootdown() {
+    let me = 1 << cpu_id();
+    if TLB_PENDING.load(Ordering::Acquire) & me != 0 {
+        x86::invlpg(TLB_ADDR.load(Ordering::Relaxed));
+        TLB_PENDING.fetch_and(!me, Ordering::Release);
+    }
+}
+
+/// Runs without the kernel lock, its holder may be waiting for this handler.
+extern "x86-interrupt" fn tlb_handler(_f: ExceptionStackFrame) {
+    handle_tlb_shootdown();
+    lapic_end_of_interrupt();
+}
@@ -0,0 +1,60 @@
+#![allow(dead_code)]
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use super::{cpu_id, ipi};
+
+/// The kernel lock.
+///
+/// The kernel delimits its critical sections by disabling interrupts, which is enough
+/// on one CPU. With several, `x86::cli` also takes this lock and `x86::sti` releases it,
+/// so that a CPU running with interrupts disabled excludes the others as well. The lock
+/// belongs to the CPU, not to the thread: it is held across a context switch and
+/// released by the next thread when it enables interrupts again.
+///
+/// A CPU waiting for the lock keeps handling TLB shootdowns, the CPU holding it may be
+/// waiting for them.
+///
+/// Interrupt handlers start with interrupts disabled but without the lock, they take it
+/// at their first `cli`. The interrupted code had interrupts enabled, so did not hold
+/// it, and the lock is released when the handler returns, see `irq::IrqGuard` and
+/// `irq::ExceptionGuard`.
+
+const NO_OWNER: u32 = u32::MAX;
+
+/// Ticket lock, so that the CPUs get it in turn.
+static NEXT_TICKET: AtomicU32 = AtomicU32::new(0);
+static NOW_SERVING: AtomicU32 = AtomicU32::new(0);
+static OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+
+/// Takes the lock for the running CPU unless it already holds it. Interrupts must be
+/// disabled.
+pub fn acquire() {
+    let cpu = cpu_id() as u32;
+    if OWNER.load(Ordering::Relaxed) == cpu {
+        return;
+    }
+    let ticket = NEXT_TICKET.fetch_add(1, Ordering::Relaxed);
+    while NOW_SERVING.load(Ordering::Acquire) != ticket {
+        ipi::handle_tlb_shootdown();
+        spin_loop();
+    }
+    OWNER.store(cpu, Ordering::Relaxed);
+}
+
+/// Releases the lock if the running CPU holds it.
+pub fn release() {
+    if OWNER.load(Ordering::Relaxed) != cpu_id() as u32 {
+        return;
+    }
+    OWNER.store(NO_OWNER, Ordering::Relaxed);
+    NOW_SERVING.fetch_add(1, Ordering::Release);
+}
+
+/// Whether the running CPU holds the lock.
+pub fn held() -> bool {
+    OWNER.load(Ordering::Relaxed) == cpu_id() as u32
+}
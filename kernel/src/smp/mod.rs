@@ -0,0 +1,44 @@
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub use percpu::{cpu_id, this_cpu};
+
+pub mod boot;
+pub mod ipi;
+pub mod klock;
+pub mod percpu;
+
+/// Symmetric multiprocessing.
+///
+/// The bootstrap processor runs the boot and then starts the application processors
+/// listed by the ACPI MADT, see `boot`. Every CPU has its own GDT, TSS, `PerCpu` data in
+/// FS, idle thread and run queue, and runs the threads concurrently, but the kernel
+/// itself is serialized by the kernel lock, see `klock`.
+
+pub const MAX_CPUS: usize = 8;
+
+/// Number of CPUs started, they are numbered from 0 in the order they came up.
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+pub fn is_bsp() -> bool {
+    cpu_id() == 0
+}
+
+/// The CPUs running the scheduler.
+pub fn online_cpus() -> impl Iterator<Item = usize> {
+    (0..cpu_count()).filter(|&cpu| percpu::cpu(cpu).is_online())
+}
+
+/// Starts the application processors. Called by the bootstrap processor once the
+/// scheduler and the interrupts are set up.
+pub fn init() {
+    ipi::init();
+    percpu::set_online(0);
+    let started = boot::start_aps();
+    crate::serial_println!("SMP: {} CPU(s) online", started + 1);
+}
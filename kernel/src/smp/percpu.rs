@@ -0,0 +1,92 @@
+#![allow(dead_code)]
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering},
+};
+
+use super::MAX_CPUS;
+
+/// Data private to each CPU.
+///
+/// Each CPU has a segment covering its `PerCpu` loaded in FS, so it finds its own index
+/// at `fs:0` whatever thread it runs. The fields are only written by their CPU, but may
+/// be read by the others.
+#[repr(C)]
+pub struct PerCpu {
+    /// Index of the CPU, at `fs:0`.
+    id: AtomicU32,
+    apic_id: AtomicU8,
+    online: AtomicBool,
+    /// Nesting depth of the hardware interrupt handlers running on the CPU.
+    pub(crate) irq_depth: AtomicU32,
+    /// Current `irq::level::Irql` of the CPU.
+    pub(crate) level: AtomicU8,
+    /// Quiescent states the CPU went through, see `sync::rcu`.
+    pub(crate) rcu_qs: AtomicU32,
+}
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self {
+            id: AtomicU32::new(0),
+            apic_id: AtomicU8::new(0),
+            online: AtomicBool::new(false),
+            irq_depth: AtomicU32::new(0),
+            level: AtomicU8::new(0),
+            rcu_qs: AtomicU32::new(0),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id.load(Ordering::Relaxed) as usize
+    }
+
+    pub fn apic_id(&self) -> u8 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+}
+
+static PERCPU: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+
+/// FS points to the `PerCpu` of the running CPU.
+static PERCPU_READY: AtomicBool = AtomicBool::new(false);
+
+pub fn cpu(id: usize) -> &'static PerCpu {
+    &PERCPU[id]
+}
+
+/// Called by `gdt::init_cpu` once FS is loaded.
+pub(crate) fn init(id: usize) {
+    PERCPU[id].id.store(id as u32, Ordering::Relaxed);
+    PERCPU_READY.store(true, Ordering::Release);
+}
+
+/// Records the local APIC of CPU ID, before it is started.
+pub(super) fn set_apic_id(id: usize, apic_id: u8) {
+    PERCPU[id].apic_id.store(apic_id, Ordering::Relaxed);
+}
+
+pub(super) fn set_online(id: usize) {
+    PERCPU[id].online.store(true, Ordering::Release);
+}
+
+/// Index of the running CPU, 0 for the bootstrap processor.
+pub fn cpu_id() -> usize {
+    if !PERCPU_READY.load(Ordering::Acquire) {
+        return 0;
+    }
+    let id: u32;
+    unsafe {
+        asm!("mov {:e}, fs:[0]", out(reg) id, options(nostack, readonly, preserves_flags));
+    }
+    id as usize
+}
+
+pub fn this_cpu() -> &'static PerCpu {
+    cpu(cpu_id())
+}
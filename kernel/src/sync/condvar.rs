@@ -0,0 +1,71 @@
+#![allow(dead_code)]
+
+use core::mem;
+
+use crate::{arch::x86::without_interrupts, thread};
+
+use super::{MutexGuard, WaitQueue};
+
+/// Condition variable, waited on with a `Mutex` held.
+///
+/// `notify_one` and `notify_all` can be called from interrupt handlers, a notification
+/// with no waiter is lost.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex of GUARD and sleeps until notified, then locks it again.
+    ///
+    /// Unlocking and going to sleep is atomic, so a notification sent once the mutex is
+    /// unlocked is not missed. Wake ups may still be spurious: the condition must be
+    /// checked again, see `wait_while`.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        // The mutex is unlocked by hand, with interrupts disabled up to the sleep.
+        mem::forget(guard);
+        without_interrupts(|| {
+            mutex.raw_lock().release_locked();
+            self.waiters.sleep();
+        });
+        mutex.lock()
+    }
+
+    /// Waits as long as COND returns `true` for the data of the mutex.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while cond(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes the highest priority waiter.
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+        thread::yield_if_needed();
+    }
+
+    /// Wakes all the waiters.
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+        thread::yield_if_needed();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
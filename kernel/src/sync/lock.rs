@@ -0,0 +1,170 @@
+#![allow(dead_code)]
+
+use core::{cell::Cell, ptr};
+
+use crate::{
+    arch::x86::without_interrupts,
+    irq,
+    thread::{self, Thread},
+};
+
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockClass};
+use super::WaitQueue;
+
+/// Longest chain of locks a donation goes through.
+const DONATION_DEPTH: usize = 8;
+
+/// Sleeping lock with priority donation.
+///
+/// A thread blocking on a held lock lends its priority to the holder, and through the
+/// lock the holder itself waits for, up to `DONATION_DEPTH` locks deep, so that a low
+/// priority holder cannot be kept from running by medium priority threads while a high
+/// priority thread waits for it. On release the lock is handed to its highest priority
+/// waiter. There are no donations in `SchedMode::Mlfqs`.
+///
+/// The threads refer to a lock by address, so it must not move while held.
+pub struct Lock {
+    /// Only touched with interrupts disabled.
+    holder: Cell<*mut Thread>,
+    waiters: WaitQueue,
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
+}
+
+unsafe impl Send for Lock {}
+unsafe impl Sync for Lock {}
+
+impl Lock {
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new() -> Self {
+        Self {
+            holder: Cell::new(ptr::null_mut()),
+            waiters: WaitQueue::new(),
+            #[cfg(feature = "lockdep")]
+            class: LockClass::new(),
+        }
+    }
+
+    /// Acquires the lock, sleeping until it is available.
+    ///
+    /// Must not be called from interrupt handlers, nor by the holder.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn acquire(&self) {
+        assert!(
+            !irq::in_interrupt(),
+            "lock acquired in an interrupt handler"
+        );
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(&self.class, self as *const Lock as usize, false);
+        without_interrupts(|| {
+            let cur = thread::current();
+            let holder = self.holder.get();
+            assert!(holder != cur, "lock acquired recursively");
+            if holder.is_null() {
+                self.take(cur);
+                return;
+            }
+            unsafe { (*cur).waiting_lock = self };
+            self.donate(unsafe { (*cur).priority });
+            // The lock is handed over by `release`.
+            self.waiters.sleep();
+            assert!(self.holder.get() == cur);
+        })
+    }
+
+    /// Acquires the lock if it is free, without sleeping.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_acquire(&self) -> bool {
+        let acquired = without_interrupts(|| {
+            if self.holder.get().is_null() {
+                self.take(thread::current());
+                true
+            } else {
+                false
+            }
+        });
+        #[cfg(feature = "lockdep")]
+        if acquired {
+            lockdep::acquire(&self.class, self as *const Lock as usize, true);
+        }
+        acquired
+    }
+
+    /// Releases the lock held by the current thread, which gives back the priority
+    /// donated through it.
+    pub fn release(&self) {
+        without_interrupts(|| self.release_locked());
+        thread::yield_if_needed();
+    }
+
+    /// Like `release`, but without yielding to a woken thread of higher priority.
+    /// Interrupts must be disabled.
+    pub(crate) fn release_locked(&self) {
+        let cur = thread::current();
+        assert!(
+            self.holder.get() == cur,
+            "lock released by a thread not holding it"
+        );
+        unsafe { (*cur).held_locks.retain(|&lock| !ptr::eq(lock, self)) };
+        self.holder.set(ptr::null_mut());
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const Lock as usize);
+        if let Some(next) = self.waiters.pop() {
+            unsafe { (*next).waiting_lock = ptr::null() };
+            self.take(next);
+            // The remaining waiters now donate to the new holder.
+            thread::update_priority(next);
+            thread::unblock(next);
+        }
+        thread::update_priority(cur);
+    }
+
+    pub fn held_by_current_thread(&self) -> bool {
+        without_interrupts(|| self.holder.get() == thread::current())
+    }
+
+    /// Id of the thread holding the lock.
+    pub fn holder_tid(&self) -> Option<u32> {
+        without_interrupts(|| {
+            let holder = self.holder.get();
+            (!holder.is_null()).then(|| unsafe { (*holder).tid() })
+        })
+    }
+
+    fn take(&self, thread: *mut Thread) {
+        self.holder.set(thread);
+        unsafe { (*thread).held_locks.push(self) };
+    }
+
+    /// Lends PRIORITY to the holder, and on along the chain of locks the holders wait
+    /// for.
+    fn donate(&self, priority: u8) {
+        if thread::mlfqs() {
+            return;
+        }
+        let mut lock: *const Lock = self;
+        for _ in 0..DONATION_DEPTH {
+            let holder = unsafe { (*lock).holder.get() };
+            if holder.is_null() || unsafe { (*holder).priority } >= priority {
+                break;
+            }
+            thread::set_effective_priority(holder, priority);
+            lock = unsafe { (*holder).waiting_lock };
+            if lock.is_null() {
+                break;
+            }
+        }
+    }
+
+    /// Highest priority among the waiters, the priority donated through the lock.
+    pub(crate) fn max_waiter_priority(&self) -> Option<u8> {
+        self.waiters.max_priority()
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        assert!(self.holder.get().is_null(), "lock dropped while held");
+    }
+}
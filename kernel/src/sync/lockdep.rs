@@ -0,0 +1,294 @@
+#![allow(dead_code)]
+
+use alloc::{collections::VecDeque, vec, vec::Vec};
+use core::{
+    panic::Location,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    arch::x86::{self, without_interrupts},
+    irq, serial_println, thread,
+    utils::singleton::Singleton,
+};
+
+/// Lock dependency validator, built with the `lockdep` feature.
+///
+/// The locks created at the same place in the source form a class. Each acquisition
+/// records that its class comes after the classes the thread already holds, with where
+/// both locks were acquired. An acquisition closing a cycle in these dependencies is
+/// reported over serial along with the chain of acquisitions seen in the opposite
+/// order: threads following both orders can deadlock, even if the timing never made
+/// them. A class taken by interrupt handlers and also held with interrupts enabled is
+/// reported as well, the handler may spin forever on the lock of the thread it
+/// interrupted.
+///
+/// Reader-writer locks count as exclusive, a waiting writer blocks the next readers.
+/// Nesting two locks of the same class is not validated. The validator turns itself off
+/// after its first report, the state it would report next is not trustworthy.
+
+type Site = &'static Location<'static>;
+
+/// The class of a lock, identified by where the lock was created.
+pub struct LockClass {
+    site: Site,
+    /// Index in `Lockdep::classes` plus one, 0 until the first acquisition.
+    index: AtomicUsize,
+}
+
+impl LockClass {
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            site: Location::caller(),
+            index: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// A lock held by a thread, in acquisition order.
+pub(crate) struct HeldLock {
+    class: usize,
+    /// Address of the lock.
+    lock: usize,
+    site: Site,
+    /// Taken by an interrupt handler, which does not depend on the locks held by the
+    /// thread it interrupted.
+    in_irq: bool,
+}
+
+struct Dependency {
+    /// Class acquired after the one holding the dependency.
+    class: usize,
+    /// Where the earlier lock was acquired.
+    held_site: Site,
+    /// Where the later lock was acquired.
+    site: Site,
+}
+
+struct Class {
+    site: Site,
+    after: Vec<Dependency>,
+    /// First acquisition by an interrupt handler.
+    irq_site: Option<Site>,
+    /// First acquisition with interrupts enabled, outside interrupt handlers.
+    irq_enabled_site: Option<Site>,
+}
+
+#[derive(Default)]
+struct Lockdep {
+    classes: Vec<Class>,
+    off: bool,
+}
+
+static LOCKDEP: Singleton<Lockdep> = Singleton::UNINIT;
+
+fn same_site(a: Site, b: Site) -> bool {
+    a.line() == b.line() && a.column() == b.column() && a.file() == b.file()
+}
+
+impl Lockdep {
+    fn class_index(&mut self, class: &LockClass) -> usize {
+        let index = class.index.load(Ordering::Relaxed);
+        if index != 0 {
+            return index - 1;
+        }
+        let index = match self
+            .classes
+            .iter()
+            .position(|c| same_site(c.site, class.site))
+        {
+            Some(index) => index,
+            None => {
+                self.classes.push(Class {
+                    site: class.site,
+                    after: Vec::new(),
+                    irq_site: None,
+                    irq_enabled_site: None,
+                });
+                self.classes.len() - 1
+            }
+        };
+        class.index.store(index + 1, Ordering::Relaxed);
+        index
+    }
+
+    /// The dependencies leading from class FROM to class TO, if any.
+    fn find_path(&self, from: usize, to: usize) -> Option<Vec<(usize, usize)>> {
+        // Edge through which each class was first reached, as (class, dependency).
+        let mut reached: Vec<Option<(usize, usize)>> = vec![None; self.classes.len()];
+        let mut queue = VecDeque::from([from]);
+        while let Some(class) = queue.pop_front() {
+            for (i, dep) in self.classes[class].after.iter().enumerate() {
+                if dep.class == from || reached[dep.class].is_some() {
+                    continue;
+                }
+                reached[dep.class] = Some((class, i));
+                if dep.class == to {
+                    let mut path = Vec::new();
+                    let mut cur = to;
+                    while let Some(edge) = reached[cur] {
+                        path.push(edge);
+                        cur = edge.0;
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                queue.push_back(dep.class);
+            }
+        }
+        None
+    }
+
+    /// Records that CLASS, acquired at SITE, comes after HELD. Returns false if the
+    /// opposite order was seen before, after reporting it.
+    fn add_dependency(&mut self, held: &HeldLock, class: usize, site: Site) -> bool {
+        if self.classes[held.class]
+            .after
+            .iter()
+            .any(|dep| dep.class == class)
+        {
+            return true;
+        }
+        if let Some(path) = self.find_path(class, held.class) {
+            self.report_inversion(held, class, site, &path);
+            return false;
+        }
+        self.classes[held.class].after.push(Dependency {
+            class,
+            held_site: held.site,
+            site,
+        });
+        true
+    }
+
+    /// Records how CLASS is acquired at SITE. Returns false if it is taken both by
+    /// interrupt handlers and with interrupts enabled, after reporting it.
+    fn mark_usage(&mut self, class: usize, site: Site, in_irq: bool, irqs_enabled: bool) -> bool {
+        let class = &mut self.classes[class];
+        if in_irq && class.irq_site.is_none() {
+            class.irq_site = Some(site);
+        } else if irqs_enabled && class.irq_enabled_site.is_none() {
+            class.irq_enabled_site = Some(site);
+        } else {
+            return true;
+        }
+        let (Some(irq_site), Some(irq_enabled_site)) = (class.irq_site, class.irq_enabled_site)
+        else {
+            return true;
+        };
+        serial_println!("lockdep: lock used by interrupt handlers without disabling them");
+        serial_println!("  lock created at {}", class.site);
+        serial_println!("  taken by an interrupt handler at {}", irq_site);
+        serial_println!("  taken with interrupts enabled at {}", irq_enabled_site);
+        self.turn_off();
+        false
+    }
+
+    fn report_inversion(
+        &mut self,
+        held: &HeldLock,
+        class: usize,
+        site: Site,
+        path: &[(usize, usize)],
+    ) {
+        let tid = unsafe { (*thread::current()).tid() };
+        serial_println!(
+            "lockdep: possible deadlock, lock order inversion in thread {}",
+            tid
+        );
+        serial_println!(
+            "  acquiring lock created at {} at {}",
+            self.classes[class].site,
+            site
+        );
+        serial_println!(
+            "  while holding lock created at {} acquired at {}",
+            self.classes[held.class].site,
+            held.site
+        );
+        serial_println!("  after this chain in the opposite order:");
+        for &(from, i) in path {
+            let dep = &self.classes[from].after[i];
+            serial_println!(
+                "    lock created at {} acquired at {}",
+                self.classes[from].site,
+                dep.held_site
+            );
+            serial_println!(
+                "    then lock created at {} acquired at {}",
+                self.classes[dep.class].site,
+                dep.site
+            );
+        }
+        self.turn_off();
+    }
+
+    fn turn_off(&mut self) {
+        serial_println!("lockdep: turning off the validator");
+        self.off = true;
+    }
+}
+
+/// Validates the acquisition of LOCK, of class CLASS, by the caller and records it as
+/// held. Called before the acquisition may block, with the interrupts left as the
+/// caller had them. A TRYLOCK cannot block, so it only matters to the locks acquired
+/// while it is held.
+#[track_caller]
+pub fn acquire(class: &LockClass, lock: usize, trylock: bool) {
+    let site = Location::caller();
+    let in_irq = irq::in_interrupt();
+    let irqs_enabled = x86::intr_enabled();
+    without_interrupts(|| {
+        let cur = thread::current();
+        let lockdep = LOCKDEP.get_mut();
+        if cur.is_null() || lockdep.off {
+            return;
+        }
+        let index = lockdep.class_index(class);
+        let held = unsafe { &mut (*cur).lockdep_held };
+        if let Some(prev) = held.iter().find(|held| held.lock == lock) {
+            serial_println!("lockdep: recursive locking in thread {}", unsafe {
+                (*cur).tid()
+            });
+            serial_println!("  lock created at {}", class.site);
+            serial_println!("  acquired at {}", prev.site);
+            serial_println!("  acquired again at {}", site);
+            lockdep.turn_off();
+            return;
+        }
+        if !trylock {
+            for prev in held.iter() {
+                if prev.in_irq == in_irq
+                    && prev.class != index
+                    && !lockdep.add_dependency(prev, index, site)
+                {
+                    return;
+                }
+            }
+        }
+        if !lockdep.mark_usage(index, site, in_irq && !trylock, irqs_enabled && !in_irq) {
+            return;
+        }
+        held.push(HeldLock {
+            class: index,
+            lock,
+            site,
+            in_irq,
+        });
+    })
+}
+
+/// Forgets LOCK, released by the current thread.
+pub fn release(lock: usize) {
+    without_interrupts(|| {
+        let cur = thread::current();
+        if cur.is_null() {
+            return;
+        }
+        let held = unsafe { &mut (*cur).lockdep_held };
+        if let Some(pos) = held.iter().rposition(|held| held.lock == lock) {
+            held.remove(pos);
+        }
+    })
+}
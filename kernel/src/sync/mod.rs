@@ -0,0 +1,23 @@
+#![allow(dead_code, unused_imports)]
+
+pub use condvar::Condvar;
+pub use lock::Lock;
+pub use mutex::{Mutex, MutexGuard};
+pub use rcu::{call_rcu, rcu_read_lock, synchronize_rcu, RcuReadGuard};
+pub use rculist::RcuList;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use spinlock::{SpinLock, SpinLockGuard};
+pub use waitqueue::WaitQueue;
+
+pub mod condvar;
+pub mod lock;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+pub mod mutex;
+pub mod rcu;
+pub mod rculist;
+pub mod rwlock;
+pub mod semaphore;
+pub mod spinlock;
+pub mod waitqueue;
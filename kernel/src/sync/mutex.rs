@@ -0,0 +1,111 @@
+#![allow(dead_code)]
+
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use super::Lock;
+
+/// Sleeping mutual exclusion around a T, built on `Lock`: the owner is tracked and the
+/// waiters donate their priority to it.
+///
+/// Must not be used from interrupt handlers.
+pub struct Mutex<T> {
+    lock: Lock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            lock: Lock::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Sleeps until the mutex is available and locks it.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.lock.acquire();
+        MutexGuard::new(self)
+    }
+
+    /// Locks the mutex if it is available, without sleeping.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.lock.try_acquire().then(|| MutexGuard::new(self))
+    }
+
+    /// Id of the thread holding the mutex.
+    pub fn owner(&self) -> Option<u32> {
+        self.lock.holder_tid()
+    }
+
+    pub fn is_locked_by_current_thread(&self) -> bool {
+        self.lock.held_by_current_thread()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    pub(super) fn raw_lock(&self) -> &Lock {
+        &self.lock
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Access to the data of a locked `Mutex`, which is unlocked when the guard is dropped.
+/// The guard belongs to the thread that locked the mutex.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>) -> Self {
+        Self {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.lock.release();
+    }
+}
@@ -0,0 +1,118 @@
+#![allow(dead_code)]
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    marker::PhantomData,
+    mem,
+    sync::atomic::{fence, Ordering},
+};
+
+use crate::{
+    arch::x86::without_interrupts,
+    irq::{
+        self,
+        level::{self, Irql},
+    },
+    smp::{self, ipi, percpu},
+    thread, time,
+    utils::singleton::Singleton,
+};
+
+use super::WaitQueue;
+
+/// Read-copy-update.
+///
+/// Readers run between `rcu_read_lock` and the drop of its guard without taking any
+/// lock, at `Irql::DISPATCH` so that they are not preempted. A writer publishes a new
+/// version of the data, then frees the old one once every CPU has gone through a
+/// quiescent state, a context switch or a pass through the idle loop, as no reader that
+/// could still see it is left: `synchronize_rcu` waits for that grace period, and
+/// `call_rcu` runs a callback after it without blocking the writer.
+///
+/// A grace period lasts up to a time slice of each busy CPU. Readers must not sleep.
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// Callbacks of `call_rcu` waiting for the next grace period.
+static PENDING: Singleton<Vec<Callback>> = Singleton::UNINIT;
+
+/// Where the RCU thread waits for callbacks.
+static RCU_WAKEUP: WaitQueue = WaitQueue::new();
+
+/// Read-side critical section, which ends when the guard is dropped.
+pub struct RcuReadGuard {
+    old_level: Irql,
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Starts a read-side critical section. The data read under RCU stays valid until the
+/// guard is dropped. Sections may nest, and may be used by interrupt handlers.
+pub fn rcu_read_lock() -> RcuReadGuard {
+    RcuReadGuard {
+        old_level: level::raise_level(level::current_level().max(Irql::DISPATCH)),
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        level::lower_level(self.old_level);
+    }
+}
+
+/// Records that the running CPU is outside any read-side critical section. Called on
+/// each context switch and each pass through the idle loop.
+pub(crate) fn note_quiescent() {
+    smp::this_cpu().rcu_qs.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Waits until every read-side critical section in progress has ended, so that the
+/// data unpublished before the call can be freed. Sleeps, so the caller must not be in
+/// a read-side critical section nor in an interrupt handler.
+pub fn synchronize_rcu() {
+    assert!(
+        level::current_level() == Irql::PASSIVE && !irq::in_interrupt(),
+        "synchronize_rcu in atomic context"
+    );
+    // Readers starting after the snapshot see the unpublished data gone.
+    fence(Ordering::SeqCst);
+    let me = smp::cpu_id();
+    let snapshot: Vec<(usize, u32)> = smp::online_cpus()
+        .filter(|&cpu| cpu != me)
+        .map(|cpu| (cpu, percpu::cpu(cpu).rcu_qs.load(Ordering::SeqCst)))
+        .collect();
+    // An idle CPU passes through the idle loop once woken up.
+    for &(cpu, _) in snapshot.iter() {
+        ipi::send_reschedule(cpu);
+    }
+    while snapshot
+        .iter()
+        .any(|&(cpu, qs)| percpu::cpu(cpu).rcu_qs.load(Ordering::SeqCst) == qs)
+    {
+        time::sleep(1);
+    }
+}
+
+/// Calls CALLBACK after a grace period, from the RCU thread. Does not sleep, so it can
+/// be used by interrupt handlers and read-side critical sections.
+pub fn call_rcu(callback: impl FnOnce() + Send + 'static) {
+    without_interrupts(|| PENDING.get_mut().push(Box::new(callback)));
+    RCU_WAKEUP.wake_one();
+}
+
+/// Starts the thread running the callbacks of `call_rcu`.
+pub fn init() {
+    thread::spawn(rcu_thread, 0);
+}
+
+/// Waits for a grace period for each batch of callbacks, then runs them.
+fn rcu_thread(_: usize) -> usize {
+    loop {
+        RCU_WAKEUP.wait_event(|| !PENDING.is_empty());
+        let batch = without_interrupts(|| mem::take(PENDING.get_mut()));
+        synchronize_rcu();
+        for callback in batch {
+            callback();
+        }
+    }
+}
@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+
+use alloc::boxed::Box;
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use super::{rcu, RcuReadGuard, SpinLock};
+
+/// Singly linked list read under RCU.
+///
+/// Readers walk the list in a read-side critical section without locking, while the
+/// writers, serialized by a spin lock, link new nodes in and unlink old ones. An
+/// unlinked node is freed by `call_rcu`, once no reader can be on it any more.
+pub struct RcuList<T: Send + Sync + 'static> {
+    head: AtomicPtr<Node<T>>,
+    writer: SpinLock<()>,
+}
+
+struct Node<T> {
+    value: T,
+    next: AtomicPtr<Node<T>>,
+}
+
+unsafe impl<T: Send + Sync + 'static> Send for RcuList<T> {}
+unsafe impl<T: Send + Sync + 'static> Sync for RcuList<T> {}
+
+impl<T: Send + Sync + 'static> RcuList<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            writer: SpinLock::new(()),
+        }
+    }
+
+    /// Inserts VALUE at the front, readers see it whole or not at all.
+    pub fn push_front(&self, value: T) {
+        let _writer = self.writer.lock_irq();
+        let node = Box::into_raw(Box::new(Node {
+            value,
+            next: AtomicPtr::new(self.head.load(Ordering::Relaxed)),
+        }));
+        self.head.store(node, Ordering::Release);
+    }
+
+    /// Unlinks the first value matching PRED, it is dropped after a grace period.
+    /// Returns whether one was found.
+    pub fn remove_first(&self, mut pred: impl FnMut(&T) -> bool) -> bool {
+        let _writer = self.writer.lock_irq();
+        let mut link = &self.head;
+        loop {
+            let node = link.load(Ordering::Relaxed);
+            if node.is_null() {
+                return false;
+            }
+            let node_ref = unsafe { &*node };
+            if pred(&node_ref.value) {
+                // Readers on the node still find the rest of the list through it.
+                link.store(node_ref.next.load(Ordering::Relaxed), Ordering::Release);
+                let node = node as usize;
+                rcu::call_rcu(move || drop(unsafe { Box::from_raw(node as *mut Node<T>) }));
+                return true;
+            }
+            link = &node_ref.next;
+        }
+    }
+
+    /// Iterates over the values, which stay valid as long as GUARD.
+    pub fn iter<'a>(&'a self, _guard: &'a RcuReadGuard) -> Iter<'a, T> {
+        Iter {
+            next: self.head.load(Ordering::Acquire),
+            _list: PhantomData,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl<T: Send + Sync + 'static> Default for RcuList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + Sync + 'static> Drop for RcuList<T> {
+    fn drop(&mut self) {
+        // No reader is left, they borrow the list.
+        let mut node = *self.head.get_mut();
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct Iter<'a, T> {
+    next: *mut Node<T>,
+    _list: PhantomData<&'a T>,
+}
+
+impl<'a, T: 'a> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.next.is_null() {
+            return None;
+        }
+        let node = unsafe { &*self.next };
+        self.next = node.next.load(Ordering::Acquire);
+        Some(&node.value)
+    }
+}
@@ -0,0 +1,231 @@
+#![allow(dead_code)]
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{arch::x86::without_interrupts, irq, thread};
+
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockClass};
+use super::WaitQueue;
+
+/// Sleeping reader-writer lock around a T.
+///
+/// Writers have precedence: once a writer waits, new readers wait too, so a stream of
+/// readers cannot starve it. Must not be used from interrupt handlers.
+pub struct RwLock<T> {
+    /// Only touched with interrupts disabled.
+    state: Cell<RwState>,
+    readers: WaitQueue,
+    writers: WaitQueue,
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
+    data: UnsafeCell<T>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct RwState {
+    /// Readers holding the lock.
+    active_readers: usize,
+    writer: bool,
+    waiting_writers: usize,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: Cell::new(RwState {
+                active_readers: 0,
+                writer: false,
+                waiting_writers: 0,
+            }),
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+            #[cfg(feature = "lockdep")]
+            class: LockClass::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Applies F to the state, returning its result.
+    fn update_state<R>(&self, f: impl FnOnce(&mut RwState) -> R) -> R {
+        let mut state = self.state.get();
+        let ret = f(&mut state);
+        self.state.set(state);
+        ret
+    }
+
+    /// Sleeps until no writer holds or waits for the lock, and locks it shared.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        assert!(!irq::in_interrupt(), "rwlock used in an interrupt handler");
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(&self.class, self as *const Self as usize, false);
+        without_interrupts(|| {
+            while self.state.get().writer || self.state.get().waiting_writers > 0 {
+                self.readers.sleep();
+            }
+            self.update_state(|state| state.active_readers += 1);
+        });
+        RwLockReadGuard {
+            lock: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Sleeps until the lock is free, and locks it exclusive.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        assert!(!irq::in_interrupt(), "rwlock used in an interrupt handler");
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(&self.class, self as *const Self as usize, false);
+        without_interrupts(|| {
+            self.update_state(|state| state.waiting_writers += 1);
+            while self.state.get().writer || self.state.get().active_readers > 0 {
+                self.writers.sleep();
+            }
+            self.update_state(|state| {
+                state.waiting_writers -= 1;
+                state.writer = true;
+            });
+        });
+        RwLockWriteGuard {
+            lock: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let guard = without_interrupts(|| {
+            let state = self.state.get();
+            if state.writer || state.waiting_writers > 0 {
+                return None;
+            }
+            self.update_state(|state| state.active_readers += 1);
+            Some(RwLockReadGuard {
+                lock: self,
+                _not_send: PhantomData,
+            })
+        });
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(&self.class, self as *const Self as usize, true);
+        }
+        guard
+    }
+
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let guard = without_interrupts(|| {
+            let state = self.state.get();
+            if state.writer || state.active_readers > 0 {
+                return None;
+            }
+            self.update_state(|state| state.writer = true);
+            Some(RwLockWriteGuard {
+                lock: self,
+                _not_send: PhantomData,
+            })
+        });
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(&self.class, self as *const Self as usize, true);
+        }
+        guard
+    }
+
+    fn read_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const Self as usize);
+        without_interrupts(|| {
+            let readers = self.update_state(|state| {
+                state.active_readers -= 1;
+                state.active_readers
+            });
+            if readers == 0 {
+                self.writers.wake_one();
+            }
+        });
+        thread::yield_if_needed();
+    }
+
+    fn write_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const Self as usize);
+        without_interrupts(|| {
+            self.update_state(|state| state.writer = false);
+            if self.state.get().waiting_writers > 0 {
+                self.writers.wake_one();
+            } else {
+                self.readers.wake_all();
+            }
+        });
+        thread::yield_if_needed();
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
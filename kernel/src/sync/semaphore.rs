@@ -0,0 +1,69 @@
+#![allow(dead_code)]
+
+use core::cell::Cell;
+
+use crate::{arch::x86::without_interrupts, irq, thread};
+
+use super::WaitQueue;
+
+/// Counting semaphore.
+///
+/// `up` and `try_down` never sleep and can be called from interrupt handlers, to let a
+/// driver signal a thread waiting in `down`.
+pub struct Semaphore {
+    /// Only touched with interrupts disabled.
+    value: Cell<usize>,
+    waiters: WaitQueue,
+}
+
+unsafe impl Send for Semaphore {}
+unsafe impl Sync for Semaphore {}
+
+impl Semaphore {
+    pub const fn new(value: usize) -> Self {
+        Self {
+            value: Cell::new(value),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Waits for the value to become positive, then decrements it.
+    pub fn down(&self) {
+        assert!(
+            !irq::in_interrupt(),
+            "semaphore down in an interrupt handler"
+        );
+        without_interrupts(|| {
+            while self.value.get() == 0 {
+                self.waiters.sleep();
+            }
+            self.value.set(self.value.get() - 1);
+        })
+    }
+
+    /// Decrements the value if it is positive, without sleeping.
+    pub fn try_down(&self) -> bool {
+        without_interrupts(|| {
+            let value = self.value.get();
+            if value == 0 {
+                false
+            } else {
+                self.value.set(value - 1);
+                true
+            }
+        })
+    }
+
+    /// Increments the value and wakes a waiter, if any.
+    pub fn up(&self) {
+        without_interrupts(|| {
+            self.value.set(self.value.get() + 1);
+            self.waiters.wake_one();
+        });
+        thread::yield_if_needed();
+    }
+
+    pub fn value(&self) -> usize {
+        without_interrupts(|| self.value.get())
+    }
+}
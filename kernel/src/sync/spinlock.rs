@@ -0,0 +1,146 @@
+#![allow(dead_code)]
+
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::arch::x86;
+
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockClass};
+
+/// Busy-waiting lock around a T, which unlike the sleeping locks can be taken by
+/// interrupt handlers.
+///
+/// `lock` leaves the interrupts as they are, so a lock also taken by an interrupt
+/// handler must be taken with `lock_irq` everywhere else, or the handler may spin
+/// forever on the lock of the thread it interrupted. Disabling interrupts takes the
+/// kernel lock, so code holding a spin lock taken by `lock` must not disable them:
+/// another CPU may be spinning on the spin lock with the kernel lock held.
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for SpinLock<T> {}
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            #[cfg(feature = "lockdep")]
+            class: LockClass::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Spins until the lock is available and locks it.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(&self.class, self as *const Self as usize, false);
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+        SpinLockGuard::new(self)
+    }
+
+    /// Disables interrupts and locks, interrupts are restored when the guard is dropped.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock_irq(&self) -> SpinLockGuard<'_, T> {
+        let enabled = x86::intr_enabled();
+        x86::cli();
+        let mut guard = self.lock();
+        guard.restore_irq = enabled;
+        guard
+    }
+
+    /// Locks if the lock is available, without spinning.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        // The validator takes the kernel lock, it must not run with the spin lock held.
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(&self.class, self as *const Self as usize, true);
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            #[cfg(feature = "lockdep")]
+            lockdep::release(self as *const Self as usize);
+            return None;
+        }
+        Some(SpinLockGuard::new(self))
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Access to the data of a locked `SpinLock`, which is unlocked when the guard is
+/// dropped.
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    /// Interrupts were enabled before `lock_irq`.
+    restore_irq: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> SpinLockGuard<'a, T> {
+    fn new(lock: &'a SpinLock<T>) -> Self {
+        Self {
+            lock,
+            restore_irq: false,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        // Only now, the validator takes the kernel lock.
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock as *const SpinLock<T> as usize);
+        if self.restore_irq {
+            x86::sti();
+        }
+    }
+}
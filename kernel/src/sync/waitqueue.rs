@@ -0,0 +1,163 @@
+#![allow(dead_code)]
+
+use alloc::collections::VecDeque;
+use core::{cell::UnsafeCell, ptr};
+
+use crate::{
+    arch::x86::{self, without_interrupts},
+    irq,
+    thread::{self, Thread},
+    time,
+};
+
+/// Threads blocked until some condition changes, woken highest priority first.
+///
+/// The queue is only touched with interrupts disabled, so it can be woken from
+/// interrupt handlers: a driver waits with `wait_event` for its interrupt handler to
+/// make the condition true and call `wake_all`.
+pub struct WaitQueue {
+    /// Only touched with interrupts disabled.
+    waiters: UnsafeCell<VecDeque<*mut Thread>>,
+}
+
+unsafe impl Send for WaitQueue {}
+unsafe impl Sync for WaitQueue {}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: UnsafeCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Blocks the current thread on the queue until it is woken.
+    ///
+    /// Interrupts must be disabled, so that a condition checked before the call cannot
+    /// change before the thread sleeps. They are still disabled on return.
+    pub fn sleep(&self) {
+        assert!(!x86::intr_enabled());
+        let cur = thread::current();
+        unsafe { (*cur).wait_queue = self };
+        unsafe { (*self.waiters.get()).push_back(cur) };
+        thread::block();
+    }
+
+    /// Sleeps on the queue until COND returns `true`.
+    ///
+    /// COND is evaluated with interrupts disabled, so a wake up between its evaluation
+    /// and the sleep cannot be missed.
+    pub fn wait_event(&self, mut cond: impl FnMut() -> bool) {
+        assert!(!irq::in_interrupt(), "wait in an interrupt handler");
+        without_interrupts(|| {
+            while !cond() {
+                self.sleep();
+            }
+        })
+    }
+
+    /// Like `wait_event`, but gives up after about TIMEOUT_MS milliseconds. Returns the
+    /// last value of COND.
+    pub fn wait_event_timeout(&self, cond: impl FnMut() -> bool, timeout_ms: u64) -> bool {
+        self.wait_event_until(cond, time::ticks() + time::ms_to_ticks(timeout_ms))
+    }
+
+    /// Like `wait_event`, but gives up once tick DEADLINE has passed. Returns the last
+    /// value of COND.
+    pub fn wait_event_until(&self, mut cond: impl FnMut() -> bool, deadline: u64) -> bool {
+        assert!(!irq::in_interrupt(), "wait in an interrupt handler");
+        without_interrupts(|| loop {
+            if cond() {
+                return true;
+            }
+            if time::ticks() >= deadline {
+                return false;
+            }
+            match time::add_timer_at(deadline, wait_timeout, thread::current() as usize) {
+                Some(timer) => {
+                    self.sleep();
+                    time::cancel_timer(timer);
+                }
+                // Out of timers: poll the condition and the clock, letting the ticks in.
+                None => {
+                    x86::sti();
+                    thread::yield_now();
+                    x86::cli();
+                }
+            }
+        })
+    }
+
+    /// Wakes the first waiter of the highest priority, returns `false` if there was none.
+    pub fn wake_one(&self) -> bool {
+        without_interrupts(|| match self.pop() {
+            Some(thread) => {
+                thread::unblock(thread);
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Wakes every waiter, returns how many there were.
+    pub fn wake_all(&self) -> usize {
+        without_interrupts(|| {
+            let mut woken = 0;
+            while let Some(thread) = self.pop() {
+                thread::unblock(thread);
+                woken += 1;
+            }
+            woken
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        without_interrupts(|| unsafe { (*self.waiters.get()).is_empty() })
+    }
+
+    /// Removes the first waiter of the highest priority without waking it. Interrupts
+    /// must be disabled.
+    pub(crate) fn pop(&self) -> Option<*mut Thread> {
+        let waiters = unsafe { &mut *self.waiters.get() };
+        let mut best: Option<(usize, u8)> = None;
+        for (i, &waiter) in waiters.iter().enumerate() {
+            let priority = unsafe { (*waiter).priority };
+            if best.is_none_or(|(_, best)| priority > best) {
+                best = Some((i, priority));
+            }
+        }
+        let thread = waiters.remove(best?.0)?;
+        unsafe { (*thread).wait_queue = ptr::null() };
+        Some(thread)
+    }
+
+    /// Removes THREAD without waking it, returns `false` if it was not waiting here.
+    /// Interrupts must be disabled.
+    fn remove(&self, thread: *mut Thread) -> bool {
+        let waiters = unsafe { &mut *self.waiters.get() };
+        let Some(pos) = waiters.iter().position(|&waiter| waiter == thread) else {
+            return false;
+        };
+        waiters.remove(pos);
+        unsafe { (*thread).wait_queue = ptr::null() };
+        true
+    }
+
+    /// Highest priority among the waiters. Interrupts must be disabled.
+    pub(crate) fn max_priority(&self) -> Option<u8> {
+        unsafe { &*self.waiters.get() }
+            .iter()
+            .map(|&waiter| unsafe { (*waiter).priority })
+            .max()
+    }
+}
+
+/// Timer callback of `WaitQueue::wait_event_until`, wakes THREAD if it still waits.
+fn wait_timeout(thread: usize) {
+    let thread = thread as *mut Thread;
+    without_interrupts(|| unsafe {
+        let queue = (*thread).wait_queue;
+        if !queue.is_null() && (*queue).remove(thread) {
+            thread::unblock(thread);
+        }
+    })
+}
@@ -0,0 +1,112 @@
+#![allow(dead_code)]
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    cell::UnsafeCell,
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use crate::arch::x86::without_interrupts;
+
+use super::WakerSlot;
+
+/// Unbounded channel carrying values to one async receiver.
+///
+/// Sending never blocks, so interrupt handlers can send too.
+struct Channel<T> {
+    queue: UnsafeCell<VecDeque<T>>,
+    receiver: WakerSlot,
+    senders: AtomicUsize,
+    /// The receiver was dropped.
+    closed: AtomicBool,
+}
+
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
+
+impl<T> Channel<T> {
+    /// Runs F on the queue with interrupts disabled, the only way it is touched.
+    fn with_queue<R>(&self, f: impl FnOnce(&mut VecDeque<T>) -> R) -> R {
+        without_interrupts(|| f(unsafe { &mut *self.queue.get() }))
+    }
+}
+
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel {
+        queue: UnsafeCell::new(VecDeque::new()),
+        receiver: WakerSlot::new(),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    (
+        Sender {
+            channel: channel.clone(),
+        },
+        Receiver { channel },
+    )
+}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Queues VALUE, or gives it back if the receiver is gone.
+    pub fn send(&self, value: T) -> Result<(), T> {
+        if self.channel.closed.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        self.channel.with_queue(|queue| queue.push_back(value));
+        self.channel.receiver.wake();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.channel.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.channel.receiver.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value, `None` once every sender is dropped and the queue is
+    /// empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| {
+            self.channel.receiver.register(cx.waker());
+            match self.try_recv() {
+                Some(value) => Poll::Ready(Some(value)),
+                None if self.channel.senders.load(Ordering::Acquire) == 0 => Poll::Ready(None),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.channel.with_queue(|queue| queue.pop_front())
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.closed.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,142 @@
+#![allow(dead_code, unused_imports)]
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    task::Wake,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{arch::x86::without_interrupts, sync::WaitQueue, utils::singleton::Singleton};
+
+pub use channel::{channel, Receiver, Sender};
+pub use sleep::{sleep, sleep_until, Sleep};
+pub use waker::WakerSlot;
+
+pub mod channel;
+pub mod sleep;
+pub mod waker;
+
+/// Cooperative executor of kernel tasks.
+///
+/// A task is a future polled by the thread running `run`, until it completes. A pending
+/// task is polled again once its waker is woken, which is safe from interrupt handlers:
+/// the drivers keep the waker of their reader in a `WakerSlot` and wake it from their
+/// handler, the timers wake `Sleep`. The executor thread sleeps while no task is ready.
+///
+/// Tasks must not block the executor thread, they wait by returning `Poll::Pending`.
+
+pub type TaskId = u32;
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    waker: Arc<TaskWaker>,
+}
+
+struct TaskWaker {
+    id: TaskId,
+    /// The task is in the ready queue.
+    queued: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            without_interrupts(|| EXECUTOR.get_mut().ready.push_back(self.id));
+            EXECUTOR_IDLE.wake_all();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Executor {
+    /// Pending tasks, but those being polled.
+    tasks: BTreeMap<TaskId, Task>,
+    ready: VecDeque<TaskId>,
+    next_id: TaskId,
+}
+
+/// The executor is only touched with interrupts disabled, the wakers may run in
+/// interrupt handlers.
+static EXECUTOR: Singleton<Executor> = Singleton::UNINIT;
+
+/// The executor thread waiting for a task to become ready.
+static EXECUTOR_IDLE: WaitQueue = WaitQueue::new();
+
+/// `run` was called, tasks are polled by a single thread.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Queues FUTURE to be polled by the executor.
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> TaskId {
+    let id = without_interrupts(|| {
+        let executor = EXECUTOR.get_mut();
+        executor.next_id += 1;
+        let id = executor.next_id;
+        let waker = Arc::new(TaskWaker {
+            id,
+            queued: AtomicBool::new(true),
+        });
+        executor.tasks.insert(
+            id,
+            Task {
+                future: Box::pin(future),
+                waker,
+            },
+        );
+        executor.ready.push_back(id);
+        id
+    });
+    EXECUTOR_IDLE.wake_all();
+    id
+}
+
+/// Number of tasks that have not completed yet, except those being polled.
+pub fn pending_tasks() -> usize {
+    without_interrupts(|| EXECUTOR.tasks.len())
+}
+
+/// Polls the ready tasks forever, sleeping while there are none. Runs on a dedicated
+/// kernel thread, the only one polling the tasks.
+pub fn run() -> ! {
+    assert!(
+        !RUNNING.swap(true, Ordering::AcqRel),
+        "executor already running"
+    );
+    loop {
+        while let Some(mut task) = next_ready() {
+            let waker = Waker::from(task.waker.clone());
+            let mut cx = Context::from_waker(&waker);
+            if task.future.as_mut().poll(&mut cx).is_pending() {
+                let id = task.waker.id;
+                without_interrupts(|| EXECUTOR.get_mut().tasks.insert(id, task));
+            }
+        }
+        EXECUTOR_IDLE.wait_event(|| !EXECUTOR.get_mut().ready.is_empty());
+    }
+}
+
+/// Takes the next ready task out of the executor, skipping the tasks woken after they
+/// completed. A task woken while polled is put back before the next call.
+fn next_ready() -> Option<Task> {
+    without_interrupts(|| {
+        let executor = EXECUTOR.get_mut();
+        while let Some(id) = executor.ready.pop_front() {
+            if let Some(task) = executor.tasks.remove(&id) {
+                // Wakes from now on, even during the poll, queue the task again.
+                task.waker.queued.store(false, Ordering::Release);
+                return Some(task);
+            }
+        }
+        None
+    })
+}
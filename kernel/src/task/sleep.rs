@@ -0,0 +1,84 @@
+#![allow(dead_code)]
+
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::time::{self, TimerId};
+
+use super::WakerSlot;
+
+/// Future completing once tick `deadline` has passed, returned by `sleep_until`.
+///
+/// The timer is armed on the first poll and holds a reference to the waker slot until
+/// it fires or the future is dropped.
+pub struct Sleep {
+    deadline: u64,
+    timer: Option<TimerId>,
+    waker: Arc<WakerSlot>,
+}
+
+impl Sleep {
+    fn new(deadline: u64) -> Self {
+        Self {
+            deadline,
+            timer: None,
+            waker: Arc::new(WakerSlot::new()),
+        }
+    }
+
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if time::ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+        self.waker.register(cx.waker());
+        if self.timer.is_none() {
+            let slot = Arc::into_raw(self.waker.clone());
+            self.timer = time::add_timer_at(self.deadline, sleep_timeout, slot as usize);
+            if self.timer.is_none() {
+                // Out of timers: poll again until one is free or the deadline passes.
+                drop(unsafe { Arc::from_raw(slot) });
+                cx.waker().wake_by_ref();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // If the timer already fired, its callback released the reference.
+        if let Some(timer) = self.timer {
+            if time::cancel_timer(timer) {
+                drop(unsafe { Arc::from_raw(Arc::as_ptr(&self.waker)) });
+            }
+        }
+    }
+}
+
+/// Timer callback of `Sleep`, SLOT is a reference to its waker slot.
+fn sleep_timeout(slot: usize) {
+    let slot = unsafe { Arc::from_raw(slot as *const WakerSlot) };
+    slot.wake();
+}
+
+/// Completes in about MS milliseconds.
+pub async fn sleep(ms: u64) {
+    sleep_until(time::ticks() + time::ms_to_ticks(ms)).await
+}
+
+/// Completes once tick DEADLINE has passed.
+pub fn sleep_until(deadline: u64) -> Sleep {
+    Sleep::new(deadline)
+}
@@ -0,0 +1,45 @@
+#![allow(dead_code)]
+
+use core::{cell::UnsafeCell, task::Waker};
+
+use crate::arch::x86::without_interrupts;
+
+/// Room for the waker of one future, woken by an interrupt handler or another task.
+///
+/// The slot is only touched with interrupts disabled, so the waking side can run in an
+/// interrupt handler, as `WaitQueue::wake_all` can for threads.
+pub struct WakerSlot {
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+unsafe impl Send for WakerSlot {}
+unsafe impl Sync for WakerSlot {}
+
+impl WakerSlot {
+    pub const fn new() -> Self {
+        Self {
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Stores WAKER, replacing the previous one unless it wakes the same task.
+    pub fn register(&self, waker: &Waker) {
+        without_interrupts(|| {
+            let slot = unsafe { &mut *self.waker.get() };
+            if !slot.as_ref().is_some_and(|old| old.will_wake(waker)) {
+                *slot = Some(waker.clone());
+            }
+        })
+    }
+
+    /// Wakes and forgets the registered waker, if any.
+    pub fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    pub fn take(&self) -> Option<Waker> {
+        without_interrupts(|| unsafe { (*self.waker.get()).take() })
+    }
+}
@@ -0,0 +1,115 @@
+#![allow(dead_code)]
+
+use alloc::boxed::Box;
+use core::{
+    arch::{asm, global_asm},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    arch::x86::{self, intr::INTR_TABLE},
+    irq,
+    utils::BitAccess,
+};
+
+use super::{current, Thread, ThreadState};
+
+/// Lazy FPU context switching.
+///
+/// The FPU and SSE registers are only restored when a thread executes an FPU or SSE
+/// instruction. The switch sets CR0.TS so that such an instruction raises `#NM`, whose
+/// handler loads the registers from the FXSAVE area of the current thread, allocated on
+/// its first use. A thread that loaded them saves them when it is switched out, so that
+/// it can resume on any CPU.
+///
+/// The handler is written in assembly because, in a kernel built with SSE, Rust code
+/// may touch the registers before they are saved.
+
+/// FXSAVE image, 512 bytes aligned on 16.
+#[repr(C, align(16))]
+pub struct FxArea([u8; 512]);
+
+impl FxArea {
+    /// The state after `fninit`, with every SSE exception masked.
+    fn initial() -> Self {
+        let mut area = FxArea([0; 512]);
+        // FCW: all x87 exceptions masked, 64-bit precision.
+        area.0[0..2].copy_from_slice(&0x037fu16.to_le_bytes());
+        // MXCSR: all SIMD exceptions masked.
+        area.0[24..28].copy_from_slice(&0x1f80u32.to_le_bytes());
+        area
+    }
+}
+
+static FPU_ENABLED: AtomicBool = AtomicBool::new(false);
+
+global_asm!(
+    ".global fpu_trap_entry",
+    "fpu_trap_entry:",
+    "    clts",
+    "    push eax",
+    "    push ecx",
+    "    push edx",
+    "    push ebp",
+    "    mov ebp, esp",
+    "    and esp, -16",
+    "    cld",
+    "    call {take}",
+    "    mov esp, ebp",
+    "    pop ebp",
+    "    fxrstor [eax]",
+    "    pop edx",
+    "    pop ecx",
+    "    pop eax",
+    "    iretd",
+    take = sym fpu_take,
+);
+
+extern "C" {
+    fn fpu_trap_entry();
+}
+
+/// Returns the area to load the registers of the current thread from.
+extern "C" fn fpu_take() -> *mut FxArea {
+    let _exception = irq::exception_enter();
+    let cur = current();
+    unsafe { &mut (*cur).fpu }
+        .get_or_insert_with(|| Box::new(FxArea::initial()))
+        .as_mut() as *mut FxArea
+}
+
+/// Installs the `#NM` handler. Without FXSR the threads must not use the FPU.
+pub fn init() {
+    if !x86::has_fxsr() {
+        return;
+    }
+    INTR_TABLE
+        .get_mut()
+        .device_not_available
+        .set_handle_addr(fpu_trap_entry as *const () as usize);
+    FPU_ENABLED.store(true, Ordering::Relaxed);
+    x86::stts();
+}
+
+/// Sets CR0.TS on an application processor.
+pub(crate) fn init_ap() {
+    if FPU_ENABLED.load(Ordering::Relaxed) {
+        x86::stts();
+    }
+}
+
+/// Called on each switch away from PREV, with interrupts disabled. CR0.TS is clear if
+/// PREV loaded its registers since it was switched in.
+pub(super) fn switch_from(prev: *mut Thread) {
+    if !FPU_ENABLED.load(Ordering::Relaxed) || x86::cr0().get_bit(x86::CR0_TS) {
+        return;
+    }
+    unsafe {
+        if (*prev).state != ThreadState::Dying {
+            if let Some(area) = (*prev).fpu.as_deref_mut() {
+                asm!("fxsave [{}]", in(reg) area as *mut FxArea, options(nostack, preserves_flags));
+            }
+        }
+    }
+    x86::stts();
+}
@@ -0,0 +1,117 @@
+#![allow(dead_code)]
+
+use crate::{arch::x86::without_interrupts, time::TIMER_FREQ, utils::fixed::Fixed};
+
+use super::{
+    current, yield_if_needed, Scheduler, Thread, ThreadState, PRI_MAX, PRI_MIN, SCHEDULER,
+};
+
+/// 4.4BSD multi-level feedback queue scheduler.
+///
+/// Each thread has a `nice` value and a `recent_cpu` estimate of the CPU time it used
+/// recently. Every 4 ticks the priorities are recomputed as
+/// `PRI_MAX - recent_cpu / 4 - nice * 2`, so threads using the CPU sink while the
+/// others rise. Every second, `recent_cpu` decays at a rate depending on `load_avg`, the
+/// average number of threads ready to run over the last minute.
+
+pub const NICE_MIN: i32 = -20;
+pub const NICE_DEFAULT: i32 = 0;
+pub const NICE_MAX: i32 = 20;
+
+/// Ticks between two recomputations of the priorities.
+const PRIORITY_PERIOD: u64 = 4;
+
+/// Seconds of statistics caught up after a long tickless idle period, the load is
+/// negligible beyond that anyway.
+const MAX_CATCH_UP_SECS: u64 = 600;
+
+pub(super) fn thread_priority(thread: &Thread) -> u8 {
+    let priority = PRI_MAX as i32 - thread.recent_cpu.div_int(4).trunc() - thread.nice * 2;
+    priority.clamp(PRI_MIN as i32, PRI_MAX as i32) as u8
+}
+
+/// Updates the statistics for the ELAPSED ticks of CPU up to tick NOW. The periodic
+/// updates follow the clock of the bootstrap processor. Interrupts must be disabled.
+pub(super) fn tick(sched: &mut Scheduler, cpu: usize, now: u64, elapsed: u64) {
+    let cur = sched.cpus[cpu].current;
+    if cur != sched.cpus[cpu].idle {
+        unsafe { (*cur).recent_cpu = (*cur).recent_cpu.add_int(elapsed as i32) };
+    }
+    if cpu != 0 {
+        return;
+    }
+    let before = now.saturating_sub(elapsed);
+    let seconds = now / TIMER_FREQ as u64 - before / TIMER_FREQ as u64;
+    for _ in 0..seconds.min(MAX_CATCH_UP_SECS) {
+        update_load_avg(sched);
+        update_recent_cpu(sched);
+    }
+    if seconds > 0 || now / PRIORITY_PERIOD != before / PRIORITY_PERIOD {
+        update_priorities(sched);
+    }
+}
+
+/// `load_avg = 59/60 * load_avg + 1/60 * ready_threads`, the running threads counting
+/// as ready unless they are idle threads.
+fn update_load_avg(sched: &mut Scheduler) {
+    let ready = sched
+        .cpus
+        .iter()
+        .map(|cpu| {
+            cpu.ready.len() as i32 + (!cpu.current.is_null() && cpu.current != cpu.idle) as i32
+        })
+        .sum::<i32>();
+    sched.load_avg = Fixed::from_ratio(59, 60) * sched.load_avg + Fixed::from_ratio(ready, 60);
+}
+
+/// `recent_cpu = 2 * load_avg / (2 * load_avg + 1) * recent_cpu + nice`.
+fn update_recent_cpu(sched: &mut Scheduler) {
+    let twice_load = sched.load_avg.mul_int(2);
+    let decay = twice_load / twice_load.add_int(1);
+    for &thread in sched.all.iter() {
+        if !sched.is_idle_thread(thread) {
+            unsafe {
+                (*thread).recent_cpu = (decay * (*thread).recent_cpu).add_int((*thread).nice)
+            };
+        }
+    }
+}
+
+fn update_priorities(sched: &mut Scheduler) {
+    for i in 0..sched.all.len() {
+        let thread = sched.all[i];
+        if !sched.is_idle_thread(thread) && unsafe { (*thread).state } != ThreadState::Dying {
+            let priority = thread_priority(unsafe { &*thread });
+            sched.set_priority(thread, priority);
+        }
+    }
+}
+
+/// Sets the nice value of the current thread, yielding if it no longer has the highest
+/// priority.
+pub fn set_nice(nice: i32) {
+    assert!((NICE_MIN..=NICE_MAX).contains(&nice));
+    without_interrupts(|| unsafe {
+        let cur = current();
+        (*cur).nice = nice;
+        if SCHEDULER.mode == super::SchedMode::Mlfqs {
+            let priority = thread_priority(&*cur);
+            SCHEDULER.get_mut().set_priority(cur, priority);
+        }
+    });
+    yield_if_needed();
+}
+
+pub fn get_nice() -> i32 {
+    unsafe { (*current()).nice }
+}
+
+/// Recent CPU time of the current thread, in ticks.
+pub fn get_recent_cpu() -> Fixed {
+    without_interrupts(|| unsafe { (*current()).recent_cpu })
+}
+
+/// System load average.
+pub fn get_load_avg() -> Fixed {
+    without_interrupts(|| SCHEDULER.load_avg)
+}
@@ -1,15 +1,731 @@
 #![allow(dead_code)]
 
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    mem, ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::{
+    arch::x86::{self, switch, without_interrupts},
+    irq::{
+        self,
+        level::{self, Irql},
+    },
+    mm::{
+        addr_space::AddressSpace,
+        page::{PAGE_ALLOC, PAGE_SIZE},
+    },
+    smp::{self, ipi, MAX_CPUS},
+    sync::{rcu, Lock, WaitQueue},
+    time, user,
+    utils::{fixed::Fixed, singleton::Singleton},
+};
+
+use fpu::FxArea;
+use runqueue::RunQueue;
+use tls::TlsBlock;
+
+pub mod fpu;
+pub mod mlfqs;
+pub mod runqueue;
+pub mod tls;
+
+/// Kernel threads.
+///
+/// Every thread runs in the kernel on its own stack. Switching happens only at
+/// `Irql::PASSIVE`, outside interrupt handlers, with interrupts disabled while the
+/// scheduler state is touched.
+///
+/// The highest priority ready thread always runs, threads of the same priority run
+/// round-robin. The timer charges the running thread each tick, and once its time slice
+/// is used up it is preempted on return from the interrupt. The idle thread runs when no
+/// other thread is ready.
+///
+/// In `SchedMode::Mlfqs` the priorities are not set by the threads but computed by the
+/// 4.4BSD scheduler, see `mlfqs`.
+///
+/// Each CPU has its own run queue and idle thread. A thread made ready goes to an idle
+/// CPU if there is one, else to the CPU running the lowest priority thread, which is
+/// interrupted if it should switch. A CPU with nothing queued steals from the busiest
+/// one, so the priorities are only followed strictly within a CPU.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ThreadState {
     Running,
     Ready,
     Blocking,
-    Dying
+    Dying,
+}
+
+pub type ThreadFn = fn(usize) -> usize;
+
+/// How priorities are assigned, chosen at boot by `init`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchedMode {
+    /// Threads set their own priority, locks donate priorities.
+    #[default]
+    Priority,
+    /// Multi-level feedback queue scheduler.
+    Mlfqs,
 }
 
+pub const PRI_MIN: u8 = 0;
+pub const PRI_DEFAULT: u8 = 31;
+pub const PRI_MAX: u8 = 63;
+pub const PRI_COUNT: usize = PRI_MAX as usize + 1;
+
+const KERNEL_STACK_PAGES: usize = 4;
+pub(crate) const KERNEL_STACK_SIZE: usize = KERNEL_STACK_PAGES * PAGE_SIZE;
+
+/// Written at the bottom of each kernel stack to detect overflows.
+const STACK_MAGIC: u32 = 0xcd6a_bf4b;
+
+/// Default time slice, in timer ticks.
+pub const DEFAULT_TIME_SLICE: u32 = 4;
+
+static TIME_SLICE: AtomicU32 = AtomicU32::new(DEFAULT_TIME_SLICE);
+
 #[repr(C)]
 pub struct Thread {
+    /// Saved stack pointer while switched out, must stay the first field for
+    /// `switch_context`.
     stack: *mut u8,
+    tid: u32,
+    /// Process the thread runs in, 0 for kernel threads.
     pid: u32,
-    state: ThreadState
-}
\ No newline at end of file
+    state: ThreadState,
+    /// CPU running the thread, whose run queue holds it, or which ran it last.
+    cpu: usize,
+    /// Lowest address of the kernel stack, null for the boot thread.
+    kstack: *mut u8,
+    entry: Option<(ThreadFn, usize)>,
+    exit_code: usize,
+    /// Thread blocked in `JoinHandle::join` on this one.
+    joiner: *mut Thread,
+    /// No `JoinHandle` refers to the thread, it is freed as soon as it dies.
+    detached: bool,
+    /// Timer ticks spent running.
+    ticks: u64,
+    /// Ticks used of the current time slice.
+    slice_ticks: u32,
+    /// Priority set by `set_priority`.
+    base_priority: u8,
+    /// Effective priority, raised above the base one by donations.
+    pub(crate) priority: u8,
+    /// Lock the thread is blocked on in `Lock::acquire`.
+    pub(crate) waiting_lock: *const Lock,
+    pub(crate) held_locks: Vec<*const Lock>,
+    /// Locks held, for the validator.
+    #[cfg(feature = "lockdep")]
+    pub(crate) lockdep_held: Vec<crate::sync::lockdep::HeldLock>,
+    /// Queue the thread sleeps on in `WaitQueue::sleep`.
+    pub(crate) wait_queue: *const WaitQueue,
+    nice: i32,
+    recent_cpu: Fixed,
+    /// FPU and SSE registers saved while the thread is switched out, allocated on the
+    /// first use, see `fpu`.
+    fpu: Option<Box<FxArea>>,
+    /// Thread-local values, see `tls`.
+    tls: Box<TlsBlock>,
+    /// Address space of the ring 3 code of the thread, `None` for kernel threads.
+    addr_space: Option<Arc<AddressSpace>>,
+}
+
+impl Thread {
+    fn new(
+        kstack: *mut u8,
+        stack: *mut u8,
+        entry: Option<(ThreadFn, usize)>,
+        priority: u8,
+    ) -> Self {
+        Self {
+            stack,
+            tid: alloc_tid(),
+            pid: 0,
+            state: ThreadState::Ready,
+            cpu: smp::cpu_id(),
+            kstack,
+            entry,
+            exit_code: 0,
+            joiner: ptr::null_mut(),
+            detached: false,
+            ticks: 0,
+            slice_ticks: 0,
+            base_priority: priority,
+            priority,
+            waiting_lock: ptr::null(),
+            held_locks: Vec::new(),
+            #[cfg(feature = "lockdep")]
+            lockdep_held: Vec::new(),
+            wait_queue: ptr::null(),
+            nice: mlfqs::NICE_DEFAULT,
+            recent_cpu: Fixed::ZERO,
+            fpu: None,
+            tls: TlsBlock::new(),
+            addr_space: None,
+        }
+    }
+
+    pub fn tid(&self) -> u32 {
+        self.tid
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn state(&self) -> ThreadState {
+        self.state
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn base_priority(&self) -> u8 {
+        self.base_priority
+    }
+
+    pub fn nice(&self) -> i32 {
+        self.nice
+    }
+
+    pub fn recent_cpu(&self) -> Fixed {
+        self.recent_cpu
+    }
+
+    fn check_stack(&self) {
+        if !self.kstack.is_null() {
+            let magic = unsafe { (self.kstack as *const u32).read() };
+            assert!(
+                magic == STACK_MAGIC,
+                "kernel stack overflow in thread {}",
+                self.tid
+            );
+        }
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        let this = self as *mut Thread;
+        without_interrupts(|| SCHEDULER.get_mut().all.retain(|&thread| thread != this));
+        if !self.kstack.is_null() {
+            without_interrupts(|| {
+                PAGE_ALLOC
+                    .get_mut()
+                    .free_page(self.kstack, KERNEL_STACK_PAGES)
+            });
+        }
+    }
+}
+
+/// Scheduling state of one CPU.
+#[derive(Default)]
+struct CpuSched {
+    current: *mut Thread,
+    /// Runs when no thread is ready, it is never in a ready queue.
+    idle: *mut Thread,
+    ready: RunQueue,
+    /// The current thread should be preempted on return from the interrupt.
+    need_resched: bool,
+}
+
+impl CpuSched {
+    fn is_idle(&self) -> bool {
+        self.current == self.idle && self.ready.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct Scheduler {
+    cpus: [CpuSched; MAX_CPUS],
+    next_tid: u32,
+    mode: SchedMode,
+    /// Every thread not freed yet, for the statistics of `SchedMode::Mlfqs`.
+    all: Vec<*mut Thread>,
+    load_avg: Fixed,
+}
+
+impl Scheduler {
+    /// See `set_effective_priority`.
+    fn set_priority(&mut self, thread: *mut Thread, priority: u8) {
+        unsafe {
+            let cpu = (*thread).cpu;
+            let sched = &mut self.cpus[cpu];
+            if (*thread).state == ThreadState::Ready && thread != sched.idle {
+                sched.ready.remove(thread);
+                (*thread).priority = priority;
+                sched.ready.push(thread);
+            } else {
+                (*thread).priority = priority;
+            }
+            if sched
+                .ready
+                .highest_priority()
+                .is_some_and(|highest| highest > (*sched.current).priority)
+            {
+                self.resched(cpu);
+            }
+        }
+    }
+
+    fn is_idle_thread(&self, thread: *mut Thread) -> bool {
+        self.cpus.iter().any(|cpu| cpu.idle == thread)
+    }
+
+    /// Requests CPU to call the scheduler, interrupting it if it is another one.
+    fn resched(&mut self, cpu: usize) {
+        if !self.cpus[cpu].need_resched {
+            self.cpus[cpu].need_resched = true;
+            if cpu != smp::cpu_id() {
+                ipi::send_reschedule(cpu);
+            }
+        }
+    }
+
+    /// CPU to queue THREAD on: an idle one, preferably the one it ran on last, else the
+    /// one running the lowest priority thread.
+    fn select_cpu(&self, thread: *mut Thread) -> usize {
+        let last = unsafe { (*thread).cpu };
+        if self.cpus[last].is_idle() {
+            return last;
+        }
+        smp::online_cpus()
+            .min_by_key(|&cpu| {
+                let sched = &self.cpus[cpu];
+                let priority = unsafe { (*sched.current).priority };
+                (!sched.is_idle(), priority, sched.ready.len(), cpu != last)
+            })
+            .unwrap_or(last)
+    }
+
+    /// Takes the next thread queued on the CPU with the most ready threads, for CPU
+    /// which has none.
+    fn steal(&mut self, cpu: usize) -> Option<*mut Thread> {
+        let victim = smp::online_cpus()
+            .filter(|&other| other != cpu)
+            .max_by_key(|&other| self.cpus[other].ready.len())?;
+        self.cpus[victim].ready.pop()
+    }
+}
+
+static SCHEDULER: Singleton<Scheduler> = Singleton::UNINIT;
+
+/// Turns the boot flow into the first thread and creates the idle thread, before any
+/// other thread function is used. MODE selects how priorities are assigned.
+pub fn init(mode: SchedMode) {
+    SCHEDULER.get_mut().mode = mode;
+    fpu::init();
+    let mut main = Thread::new(ptr::null_mut(), ptr::null_mut(), None, PRI_DEFAULT);
+    main.state = ThreadState::Running;
+    main.detached = true;
+    let main = Box::into_raw(Box::new(main));
+    tls::init(main);
+    let idle = new_thread(idle_thread, 0, PRI_MIN);
+    without_interrupts(|| {
+        let sched = SCHEDULER.get_mut();
+        sched.all.push(main);
+        sched.cpus[0].current = main;
+        sched.cpus[0].idle = idle;
+    });
+}
+
+/// Turns the boot flow of an application processor, running on KSTACK from
+/// `new_kstack`, into the idle thread of the CPU. Interrupts must be disabled.
+pub(crate) fn init_ap(kstack: *mut u8) {
+    fpu::init_ap();
+    let mut idle = Thread::new(kstack, ptr::null_mut(), None, PRI_MIN);
+    idle.state = ThreadState::Running;
+    idle.detached = true;
+    let idle = Box::into_raw(Box::new(idle));
+    tls::bind(idle);
+    tls::switch_to(idle);
+    let sched = SCHEDULER.get_mut();
+    sched.all.push(idle);
+    let cpu = &mut sched.cpus[smp::cpu_id()];
+    cpu.current = idle;
+    cpu.idle = idle;
+}
+
+pub fn sched_mode() -> SchedMode {
+    SCHEDULER.mode
+}
+
+pub fn mlfqs() -> bool {
+    sched_mode() == SchedMode::Mlfqs
+}
+
+fn idle_thread(_: usize) -> usize {
+    idle_loop()
+}
+
+pub fn idle_loop() -> ! {
+    loop {
+        rcu::note_quiescent();
+        time::idle();
+    }
+}
+
+fn alloc_tid() -> u32 {
+    without_interrupts(|| {
+        let sched = SCHEDULER.get_mut();
+        sched.next_tid += 1;
+        sched.next_tid
+    })
+}
+
+/// The running thread.
+pub fn current() -> *mut Thread {
+    tls::current_thread()
+}
+
+/// Starts a thread running `func(arg)`, its return value is the exit code.
+pub fn spawn(func: ThreadFn, arg: usize) -> JoinHandle {
+    spawn_with_priority(func, arg, PRI_DEFAULT)
+}
+
+/// Like `spawn`, the thread starts with priority PRIORITY and preempts the caller if
+/// that is higher than its own. PRIORITY is ignored in `SchedMode::Mlfqs`.
+pub fn spawn_with_priority(func: ThreadFn, arg: usize, priority: u8) -> JoinHandle {
+    assert!(priority <= PRI_MAX);
+    let thread = new_thread(func, arg, priority);
+    without_interrupts(|| unsafe {
+        let cur = current();
+        (*thread).nice = (*cur).nice;
+        (*thread).recent_cpu = (*cur).recent_cpu;
+        if mlfqs() {
+            (*thread).priority = mlfqs::thread_priority(&*thread);
+            (*thread).base_priority = (*thread).priority;
+        }
+        SCHEDULER.get_mut().all.push(thread);
+        make_ready(thread);
+    });
+    yield_if_needed();
+    JoinHandle { thread }
+}
+
+/// Allocates a kernel stack of `KERNEL_STACK_SIZE` bytes, with `STACK_MAGIC` at its
+/// bottom.
+pub(crate) fn new_kstack() -> *mut u8 {
+    let kstack = without_interrupts(|| PAGE_ALLOC.get_mut().get_page(KERNEL_STACK_PAGES))
+        .expect("out of memory for kernel stack");
+    unsafe { (kstack as *mut u32).write(STACK_MAGIC) };
+    kstack
+}
+
+/// Allocates a thread ready to run `func(arg)`, but not queued yet.
+fn new_thread(func: ThreadFn, arg: usize, priority: u8) -> *mut Thread {
+    let kstack = new_kstack();
+    let stack = unsafe { switch::prepare_stack(kstack as usize + KERNEL_STACK_SIZE, thread_start) };
+    let thread = Box::into_raw(Box::new(Thread::new(
+        kstack,
+        stack as *mut u8,
+        Some((func, arg)),
+        priority,
+    )));
+    tls::bind(thread);
+    thread
+}
+
+extern "C" fn thread_start(prev: *mut usize) -> ! {
+    schedule_tail(prev as *mut Thread);
+    x86::sti();
+    let (func, arg) = unsafe { (*current()).entry.take() }.expect("thread started twice");
+    exit(func(arg))
+}
+
+/// Switches to the next ready thread. The current thread must already have left the
+/// `Running` state, and interrupts must be disabled.
+fn schedule() {
+    assert!(!x86::intr_enabled());
+    assert!(
+        level::current_level() == Irql::PASSIVE && !irq::in_interrupt(),
+        "scheduling in atomic context"
+    );
+    rcu::note_quiescent();
+    let me = smp::cpu_id();
+    let sched = SCHEDULER.get_mut();
+    let cur = sched.cpus[me].current;
+    unsafe { (*cur).check_stack() };
+    let next = match sched.cpus[me].ready.pop() {
+        Some(next) => next,
+        None => sched.steal(me).unwrap_or(sched.cpus[me].idle),
+    };
+    sched.cpus[me].current = next;
+    sched.cpus[me].need_resched = false;
+    unsafe {
+        (*next).state = ThreadState::Running;
+        (*next).slice_ticks = 0;
+        (*next).cpu = me;
+    }
+    if next != cur {
+        // Interrupts from ring 3 land on the kernel stack of the next thread.
+        let kstack = unsafe { (*next).kstack };
+        if !kstack.is_null() {
+            user::set_kernel_stack(kstack as usize + KERNEL_STACK_SIZE);
+        }
+        AddressSpace::activate(unsafe { (*next).addr_space.as_deref() });
+        tls::switch_to(next);
+        fpu::switch_from(cur);
+        let prev = unsafe { switch::switch_context(cur as *mut usize, next as *mut usize) };
+        schedule_tail(prev as *mut Thread);
+    }
+}
+
+/// Finishes a switch away from PREV on the stack of the new thread.
+fn schedule_tail(prev: *mut Thread) {
+    unsafe {
+        if (*prev).state == ThreadState::Dying && (*prev).detached {
+            drop(Box::from_raw(prev));
+        }
+    }
+}
+
+/// Makes the current thread run in address space SPACE, or in the kernel page
+/// directory for `None`, and returns the previous one.
+pub fn set_address_space(space: Option<Arc<AddressSpace>>) -> Option<Arc<AddressSpace>> {
+    without_interrupts(|| {
+        AddressSpace::activate(space.as_deref());
+        unsafe { mem::replace(&mut (*current()).addr_space, space) }
+    })
+}
+
+/// Makes the current thread part of process PID.
+pub(crate) fn set_pid(pid: u32) {
+    without_interrupts(|| unsafe { (*current()).pid = pid })
+}
+
+/// The address space of the current thread.
+pub fn address_space() -> Option<Arc<AddressSpace>> {
+    without_interrupts(|| unsafe { (*current()).addr_space.clone() })
+}
+
+/// Gives the CPU to the other ready threads, if any.
+pub fn yield_now() {
+    let enabled = x86::intr_enabled();
+    x86::cli();
+    let sched = &mut SCHEDULER.get_mut().cpus[smp::cpu_id()];
+    let cur = sched.current;
+    unsafe { (*cur).state = ThreadState::Ready };
+    if cur != sched.idle {
+        sched.ready.push(cur);
+    }
+    schedule();
+    if enabled {
+        x86::sti();
+    }
+}
+
+/// Charges the ELAPSED timer ticks up to tick NOW to the thread running on this CPU
+/// and requests its preemption once its time slice is used up. Called by the timer
+/// interrupt of each CPU.
+pub fn tick(now: u64, elapsed: u64) {
+    without_interrupts(|| {
+        let me = smp::cpu_id();
+        let sched = SCHEDULER.get_mut();
+        let cur = sched.cpus[me].current;
+        if cur.is_null() {
+            return;
+        }
+        unsafe {
+            (*cur).ticks += elapsed;
+            (*cur).slice_ticks = (*cur).slice_ticks.saturating_add(elapsed as u32);
+            if (*cur).slice_ticks >= TIME_SLICE.load(Ordering::Relaxed) {
+                sched.cpus[me].need_resched = true;
+            }
+        }
+        if sched.mode == SchedMode::Mlfqs {
+            mlfqs::tick(sched, me, now, elapsed);
+        }
+    })
+}
+
+/// Switches away from the interrupted thread if it used up its time slice, or if it is
+/// the idle thread and a thread is ready on any CPU. Called on return from interrupts
+/// to `Irql::PASSIVE`, with interrupts disabled.
+pub fn preempt() {
+    let sched = SCHEDULER.get_mut();
+    let cpu = &sched.cpus[smp::cpu_id()];
+    if cpu.current.is_null() {
+        return;
+    }
+    let idle = cpu.current == cpu.idle;
+    if cpu.need_resched || idle && sched.cpus.iter().any(|cpu| !cpu.ready.is_empty()) {
+        yield_now();
+    }
+}
+
+/// Sets the time slice of the round-robin scheduler, in timer ticks.
+pub fn set_time_slice(ticks: u32) {
+    TIME_SLICE.store(ticks.max(1), Ordering::Relaxed);
+}
+
+pub fn time_slice() -> u32 {
+    TIME_SLICE.load(Ordering::Relaxed)
+}
+
+/// Puts the current thread to sleep until `unblock` is called on it.
+///
+/// Interrupts must be disabled, so that the wake up cannot happen between the decision
+/// to block and this call. They are still disabled on return.
+pub fn block() {
+    assert!(!x86::intr_enabled());
+    unsafe { (*current()).state = ThreadState::Blocking };
+    schedule();
+}
+
+/// Makes THREAD, blocked by `block`, ready to run again. Safe to call from interrupt
+/// handlers.
+///
+/// Does not switch, but if THREAD has a higher priority than the current thread, the
+/// latter is preempted on return from the interrupt or at the next `yield_if_needed`.
+pub fn unblock(thread: *mut Thread) {
+    without_interrupts(|| unsafe {
+        assert!((*thread).state == ThreadState::Blocking);
+        make_ready(thread);
+    })
+}
+
+/// Queues THREAD on the CPU chosen by `Scheduler::select_cpu`, requesting a reschedule
+/// there if it has a higher priority than the thread running it or if the CPU is idle.
+/// Interrupts must be disabled.
+fn make_ready(thread: *mut Thread) {
+    let sched = SCHEDULER.get_mut();
+    let target = sched.select_cpu(thread);
+    unsafe {
+        (*thread).state = ThreadState::Ready;
+        (*thread).cpu = target;
+        let cpu = &mut sched.cpus[target];
+        cpu.ready.push(thread);
+        if (*thread).priority > (*cpu.current).priority || cpu.current == cpu.idle {
+            sched.resched(target);
+        }
+    }
+}
+
+/// Yields if a higher priority thread became ready, unless called from an interrupt
+/// handler or above `Irql::PASSIVE`, where the switch happens on return to
+/// `Irql::PASSIVE`.
+pub fn yield_if_needed() {
+    if irq::in_interrupt() || level::current_level() != Irql::PASSIVE {
+        return;
+    }
+    if without_interrupts(|| SCHEDULER.cpus[smp::cpu_id()].need_resched) {
+        yield_now();
+    }
+}
+
+/// Sets the base priority of the current thread, yielding if it no longer has the
+/// highest priority. Donations still apply on top of it.
+///
+/// Ignored in `SchedMode::Mlfqs`.
+pub fn set_priority(priority: u8) {
+    assert!(priority <= PRI_MAX);
+    if mlfqs() {
+        return;
+    }
+    without_interrupts(|| unsafe {
+        let cur = current();
+        (*cur).base_priority = priority;
+        update_priority(cur);
+    });
+    yield_if_needed();
+}
+
+/// Effective priority of the current thread.
+pub fn get_priority() -> u8 {
+    unsafe { (*current()).priority }
+}
+
+/// Recomputes the effective priority of THREAD from its base priority and the waiters
+/// of the locks it holds. Interrupts must be disabled.
+pub(crate) fn update_priority(thread: *mut Thread) {
+    if mlfqs() {
+        return;
+    }
+    let donated = unsafe { &(*thread).held_locks }
+        .iter()
+        .filter_map(|&lock| unsafe { (*lock).max_waiter_priority() })
+        .max();
+    let base = unsafe { (*thread).base_priority };
+    set_effective_priority(thread, donated.map_or(base, |donated| donated.max(base)));
+}
+
+/// Changes the effective priority of THREAD, keeping the run queue ordered and
+/// requesting a reschedule if the current thread is no longer the highest priority
+/// one. Interrupts must be disabled.
+pub(crate) fn set_effective_priority(thread: *mut Thread, priority: u8) {
+    SCHEDULER.get_mut().set_priority(thread, priority)
+}
+
+/// Terminates the current thread with exit code CODE.
+pub fn exit(code: usize) -> ! {
+    tls::destroy_values();
+    if unsafe { (*current()).pid } != 0 {
+        user::process::exit_thread(code);
+    }
+    x86::cli();
+    let cur = current();
+    unsafe {
+        assert!(
+            (*cur).held_locks.is_empty(),
+            "thread exiting with locks held"
+        );
+        (*cur).exit_code = code;
+        (*cur).state = ThreadState::Dying;
+        if !(*cur).joiner.is_null() {
+            unblock((*cur).joiner);
+        }
+    }
+    schedule();
+    unreachable!("dead thread scheduled");
+}
+
+/// Owned permission to join a thread. Dropping it detaches the thread.
+pub struct JoinHandle {
+    thread: *mut Thread,
+}
+
+impl JoinHandle {
+    pub fn tid(&self) -> u32 {
+        unsafe { (*self.thread).tid }
+    }
+
+    /// Waits for the thread to exit and returns its exit code.
+    pub fn join(self) -> usize {
+        let thread = self.thread;
+        core::mem::forget(self);
+        let enabled = x86::intr_enabled();
+        x86::cli();
+        let code = unsafe {
+            assert!(thread != current(), "thread joining itself");
+            while (*thread).state != ThreadState::Dying {
+                (*thread).joiner = current();
+                block();
+            }
+            Box::from_raw(thread).exit_code
+        };
+        if enabled {
+            x86::sti();
+        }
+        code
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        without_interrupts(|| unsafe {
+            if (*self.thread).state == ThreadState::Dying {
+                drop(Box::from_raw(self.thread));
+            } else {
+                (*self.thread).detached = true;
+            }
+        })
+    }
+}
@@ -0,0 +1,65 @@
+#![allow(dead_code)]
+
+use alloc::collections::VecDeque;
+
+use super::{Thread, PRI_COUNT};
+
+/// Ready threads, one FIFO per priority and a bitmap of the non-empty ones, so that
+/// the highest priority thread is found in constant time.
+pub struct RunQueue {
+    queues: [VecDeque<*mut Thread>; PRI_COUNT],
+    bitmap: u64,
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self {
+            queues: core::array::from_fn(|_| VecDeque::new()),
+            bitmap: 0,
+        }
+    }
+}
+
+impl RunQueue {
+    pub fn push(&mut self, thread: *mut Thread) {
+        let priority = unsafe { (*thread).priority } as usize;
+        self.queues[priority].push_back(thread);
+        self.bitmap |= 1 << priority;
+    }
+
+    /// Removes the first thread of the highest priority.
+    pub fn pop(&mut self) -> Option<*mut Thread> {
+        let priority = self.highest_priority()? as usize;
+        let thread = self.queues[priority].pop_front();
+        if self.queues[priority].is_empty() {
+            self.bitmap &= !(1 << priority);
+        }
+        thread
+    }
+
+    /// Removes THREAD, queued with its current priority.
+    pub fn remove(&mut self, thread: *mut Thread) -> bool {
+        let priority = unsafe { (*thread).priority } as usize;
+        let queue = &mut self.queues[priority];
+        let Some(pos) = queue.iter().position(|&t| t == thread) else {
+            return false;
+        };
+        queue.remove(pos);
+        if queue.is_empty() {
+            self.bitmap &= !(1 << priority);
+        }
+        true
+    }
+
+    pub fn highest_priority(&self) -> Option<u8> {
+        (self.bitmap != 0).then(|| 63 - self.bitmap.leading_zeros() as u8)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bitmap == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+}
@@ -0,0 +1,241 @@
+#![allow(dead_code)]
+
+use alloc::{
+    alloc::{alloc_zeroed, handle_alloc_error, Layout},
+    boxed::Box,
+};
+use core::{
+    arch::asm,
+    mem::{align_of, size_of},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{
+    arch::x86::{gdt, without_interrupts},
+    irq,
+    mm::page::PAGE_SIZE,
+};
+
+use super::Thread;
+
+/// Thread-local storage.
+///
+/// Each thread owns a TLS block of one page. On each switch the scheduler rebases the
+/// TLS segment of the GDT on the block of the next thread and reloads GS, so the running
+/// thread finds its block, and itself, through GS without going through the scheduler.
+///
+/// A `thread_local!` static gets an offset in the blocks on its first use, the same in
+/// every thread. Each thread initializes its own value on its first access, and the
+/// values are dropped when the thread exits.
+
+pub const TLS_SIZE: usize = PAGE_SIZE;
+const TLS_HEADER_SIZE: usize = 16;
+const MAX_TLS_KEYS: usize = 64;
+
+#[repr(C, align(16))]
+pub struct TlsBlock {
+    /// Address of the block itself, at `gs:0`.
+    this: *mut TlsBlock,
+    /// Thread owning the block, at `gs:4`.
+    thread: *mut Thread,
+    /// Bitmap of the keys whose value is initialized in this block.
+    inited: u64,
+    data: [u8; TLS_SIZE - TLS_HEADER_SIZE],
+}
+
+impl TlsBlock {
+    /// Allocates a block with no value initialized.
+    pub(super) fn new() -> Box<TlsBlock> {
+        let layout = Layout::new::<TlsBlock>();
+        let block = unsafe { alloc_zeroed(layout) } as *mut TlsBlock;
+        if block.is_null() {
+            handle_alloc_error(layout);
+        }
+        unsafe {
+            (*block).this = block;
+            Box::from_raw(block)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct KeyInfo {
+    offset: usize,
+    drop: Option<unsafe fn(*mut u8)>,
+}
+
+static mut KEYS: [KeyInfo; MAX_TLS_KEYS] = [KeyInfo {
+    offset: 0,
+    drop: None,
+}; MAX_TLS_KEYS];
+static mut KEY_COUNT: usize = 0;
+static mut NEXT_OFFSET: usize = 0;
+
+/// GS points to the block of the running thread.
+static TLS_READY: AtomicBool = AtomicBool::new(false);
+
+/// Points GS to the block of MAIN, the boot thread.
+pub(super) fn init(main: *mut Thread) {
+    without_interrupts(|| {
+        bind(main);
+        switch_to(main);
+        TLS_READY.store(true, Ordering::Release);
+    })
+}
+
+/// Records THREAD as the owner of its block, once it has its final address.
+pub(super) fn bind(thread: *mut Thread) {
+    unsafe { (*thread).tls.thread = thread };
+}
+
+/// Called on each switch to NEXT, with interrupts disabled.
+pub(super) fn switch_to(next: *mut Thread) {
+    let block = unsafe { (*next).tls.as_mut() as *mut TlsBlock };
+    gdt::set_tls(block as u32, TLS_SIZE as u32);
+}
+
+/// The block of the running thread, null before `init`.
+fn current_block() -> *mut TlsBlock {
+    if !TLS_READY.load(Ordering::Acquire) {
+        return ptr::null_mut();
+    }
+    let block: *mut TlsBlock;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) block, options(nostack, readonly, preserves_flags));
+    }
+    block
+}
+
+/// The running thread, null before `init`.
+pub fn current_thread() -> *mut Thread {
+    if !TLS_READY.load(Ordering::Acquire) {
+        return ptr::null_mut();
+    }
+    let thread: *mut Thread;
+    unsafe {
+        asm!("mov {}, gs:[4]", out(reg) thread, options(nostack, readonly, preserves_flags));
+    }
+    thread
+}
+
+unsafe fn drop_value<T>(value: *mut u8) {
+    ptr::drop_in_place(value as *mut T);
+}
+
+/// Drops the values of the current thread, including those initialized by the
+/// destructors themselves. Called by the exiting thread.
+pub(super) fn destroy_values() {
+    let block = current_block();
+    if block.is_null() {
+        return;
+    }
+    unsafe {
+        while (*block).inited != 0 {
+            let index = (*block).inited.trailing_zeros() as usize;
+            (*block).inited &= !(1 << index);
+            let KeyInfo { offset, drop } = KEYS[index];
+            if let Some(drop) = drop {
+                drop((*block).data.as_mut_ptr().add(offset));
+            }
+        }
+    }
+}
+
+/// A thread-local static, declared with `thread_local!`.
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T,
+    /// Index in `KEYS` plus one, 0 until the first use.
+    key: AtomicUsize,
+}
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            init,
+            key: AtomicUsize::new(0),
+        }
+    }
+
+    fn index(&self) -> usize {
+        let key = self.key.load(Ordering::Acquire);
+        if key != 0 {
+            return key - 1;
+        }
+        without_interrupts(|| unsafe {
+            let key = self.key.load(Ordering::Acquire);
+            if key != 0 {
+                return key - 1;
+            }
+            assert!(align_of::<T>() <= TLS_HEADER_SIZE);
+            let index = KEY_COUNT;
+            assert!(index < MAX_TLS_KEYS, "too many thread-local keys");
+            let offset = NEXT_OFFSET.next_multiple_of(align_of::<T>());
+            assert!(
+                offset + size_of::<T>() <= TLS_SIZE - TLS_HEADER_SIZE,
+                "out of thread-local storage"
+            );
+            KEYS[index] = KeyInfo {
+                offset,
+                drop: core::mem::needs_drop::<T>().then_some(drop_value::<T> as unsafe fn(*mut u8)),
+            };
+            KEY_COUNT = index + 1;
+            NEXT_OFFSET = offset + size_of::<T>();
+            self.key.store(index + 1, Ordering::Release);
+            index
+        })
+    }
+
+    /// Runs F on the value of the current thread, initializing it on the first access.
+    ///
+    /// Interrupt handlers run on the stack of whatever thread they interrupted, so they
+    /// must not use thread-local values.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        assert!(
+            !irq::in_interrupt(),
+            "thread-local accessed in an interrupt handler"
+        );
+        let index = self.index();
+        let block = current_block();
+        assert!(!block.is_null(), "thread-local accessed before threads");
+        unsafe {
+            let value = (*block).data.as_mut_ptr().add(KEYS[index].offset) as *mut T;
+            if (*block).inited & 1 << index == 0 {
+                let init = (self.init)();
+                if (*block).inited & 1 << index == 0 {
+                    value.write(init);
+                    (*block).inited |= 1 << index;
+                }
+            }
+            f(&*value)
+        }
+    }
+}
+
+/// Declares thread-local statics of type `LocalKey`, initialized per thread by their
+/// expression:
+///
+/// ```ignore
+/// thread_local! {
+///     static ERRNO: Cell<i32> = Cell::new(0);
+/// }
+/// ERRNO.with(|errno| errno.set(2));
+/// ```
+#[macro_export]
+macro_rules! thread_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::thread::tls::LocalKey<$t> = {
+            fn __init() -> $t {
+                $init
+            }
+            $crate::thread::tls::LocalKey::new(__init)
+        };
+        $crate::thread_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $crate::thread_local!($(#[$attr])* $vis static $name: $t = $init;);
+    };
+}
@@ -0,0 +1,161 @@
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{
+    arch::x86::{
+        apic::{
+            lapic_init, lapic_timer_current, lapic_timer_start, lapic_timer_stop,
+            LAPIC_TIMER_VECTOR,
+        },
+        intr::{ExceptionStackFrame, INTR_TABLE},
+        pic::{pic_disable_irq, pic_enable_irq, pit_configure_channel},
+    },
+    irq, serial_println, smp, thread,
+};
+
+use super::{now_ns, ticks, TIMER_FREQ};
+
+/// A device raising the timer interrupt, either periodically or once at a programmed
+/// deadline.
+pub trait ClockEvent: Sync {
+    fn name(&self) -> &'static str;
+
+    /// The one-shot programming of the device, `None` if it only runs periodically.
+    fn oneshot(&self) -> Option<&dyn OneShot> {
+        None
+    }
+
+    fn can_periodic(&self) -> bool {
+        true
+    }
+
+    /// How good the device is, the best one available is selected at boot.
+    fn rating(&self) -> u32;
+
+    /// Raises the interrupt FREQ times per second.
+    fn set_periodic(&self, freq: u32);
+
+    /// Stops the interrupt.
+    fn shutdown(&self);
+}
+
+/// A clock event device able to raise the interrupt once at a programmed deadline.
+pub trait OneShot: Sync {
+    /// Raises the interrupt once, DELTA_NS nanoseconds from now.
+    fn set_next_event(&self, delta_ns: u64);
+
+    /// Longest delay `set_next_event` can program.
+    fn max_delta_ns(&self) -> u64;
+}
+
+/// PIT channel 0 on IRQ 0, periodic only.
+pub struct PitEvent;
+
+pub static PIT_EVENT: PitEvent = PitEvent;
+
+impl ClockEvent for PitEvent {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+
+    fn rating(&self) -> u32 {
+        100
+    }
+
+    fn set_periodic(&self, freq: u32) {
+        pit_configure_channel(0, 2, freq);
+        pic_enable_irq(0);
+    }
+
+    fn shutdown(&self) {
+        pic_disable_irq(0);
+    }
+}
+
+/// Local APIC timer, counting at the bus clock divided by 16.
+pub struct LapicEvent;
+
+pub static LAPIC_EVENT: LapicEvent = LapicEvent;
+
+/// Local APIC timer counts per millisecond, 0 until calibrated.
+static LAPIC_COUNTS_PER_MS: AtomicU32 = AtomicU32::new(0);
+
+const LAPIC_CALIBRATE_MS: u64 = 10;
+
+impl LapicEvent {
+    /// Enables the local APIC and calibrates its timer against the monotonic clock.
+    /// Returns `false` if there is no local APIC.
+    pub fn init(&self) -> bool {
+        if !lapic_init() {
+            return false;
+        }
+        INTR_TABLE.get_mut()[LAPIC_TIMER_VECTOR as usize].set_handle_fn(lapic_timer_handler);
+        lapic_timer_start(u32::MAX, false);
+        let start = now_ns();
+        while now_ns() - start < LAPIC_CALIBRATE_MS * 1_000_000 {}
+        let counted = u32::MAX - lapic_timer_current();
+        lapic_timer_stop();
+        LAPIC_COUNTS_PER_MS.store(counted / LAPIC_CALIBRATE_MS as u32, Ordering::Relaxed);
+        serial_println!(
+            "LAPIC timer: {} counts/ms",
+            counted / LAPIC_CALIBRATE_MS as u32
+        );
+        counted != 0
+    }
+
+    fn counts_per_ms(&self) -> u64 {
+        LAPIC_COUNTS_PER_MS.load(Ordering::Relaxed) as u64
+    }
+}
+
+impl ClockEvent for LapicEvent {
+    fn name(&self) -> &'static str {
+        "lapic"
+    }
+
+    fn oneshot(&self) -> Option<&dyn OneShot> {
+        Some(self)
+    }
+
+    fn rating(&self) -> u32 {
+        150
+    }
+
+    fn set_periodic(&self, freq: u32) {
+        lapic_timer_start((self.counts_per_ms() * 1000 / freq as u64) as u32, true);
+    }
+
+    fn shutdown(&self) {
+        lapic_timer_stop();
+    }
+}
+
+impl OneShot for LapicEvent {
+    fn set_next_event(&self, delta_ns: u64) {
+        let delta_ns = delta_ns.min(self.max_delta_ns());
+        let count = (delta_ns * self.counts_per_ms() / 1_000_000).max(1);
+        lapic_timer_start(count as u32, false);
+    }
+
+    fn max_delta_ns(&self) -> u64 {
+        u32::MAX as u64 * 1_000_000 / self.counts_per_ms().max(1)
+    }
+}
+
+/// Starts the local APIC timer of an application processor, which only drives the time
+/// slices of its threads. Without a calibrated timer they are not preempted.
+pub fn start_ap_timer() {
+    if LAPIC_COUNTS_PER_MS.load(Ordering::Relaxed) != 0 {
+        LAPIC_EVENT.set_periodic(TIMER_FREQ);
+    }
+}
+
+extern "x86-interrupt" fn lapic_timer_handler(_f: ExceptionStackFrame) {
+    let _irq = irq::local_irq_enter();
+    if smp::is_bsp() {
+        super::clock_event_handler();
+    } else {
+        thread::tick(ticks(), 1);
+    }
+}
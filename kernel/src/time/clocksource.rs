@@ -0,0 +1,64 @@
+#![allow(dead_code)]
+
+use crate::arch::x86::{
+    pic::{pic_read_irr, pit_read_counter, pit_reload, PIT_HZ},
+    without_interrupts,
+};
+
+use super::TICKS;
+
+/// A free running counter the monotonic clock is read from.
+pub trait ClockSource: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Current value of the counter.
+    fn read(&self) -> u64;
+
+    /// Counter increments per second.
+    fn frequency(&self) -> u64;
+
+    /// How good the source is, the best one available is selected at boot.
+    fn rating(&self) -> u32;
+
+    /// Whether the counter only advances with the periodic tick.
+    fn needs_tick(&self) -> bool {
+        false
+    }
+}
+
+/// The PIT channel 0 counter extended by the tick count, always available but slow to
+/// read and only accurate to a PIT cycle.
+pub struct PitClock;
+
+pub static PIT_CLOCK: PitClock = PitClock;
+
+impl ClockSource for PitClock {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+
+    fn read(&self) -> u64 {
+        without_interrupts(|| {
+            let reload = pit_reload(0) as u64;
+            let mut ticks = unsafe { TICKS };
+            let counter = pit_read_counter(0) as u64;
+            // The counter reloaded but the tick interrupt is still pending.
+            if pic_read_irr() & 1 != 0 && counter > reload / 2 {
+                ticks += 1;
+            }
+            ticks * reload + (reload - counter)
+        })
+    }
+
+    fn frequency(&self) -> u64 {
+        PIT_HZ as u64
+    }
+
+    fn rating(&self) -> u32 {
+        110
+    }
+
+    fn needs_tick(&self) -> bool {
+        true
+    }
+}
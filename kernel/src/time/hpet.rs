@@ -0,0 +1,285 @@
+#![allow(dead_code)]
+
+use core::{
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use crate::{
+    acpi::{self, GenericAddress, ADDRESS_SPACE_MEMORY},
+    arch::x86::{pic::pic_enable_irq, without_interrupts},
+    io::rtc,
+    mm, serial_println,
+    utils::BitAccess,
+};
+
+use super::{
+    clockevent::{ClockEvent, OneShot},
+    clocksource::ClockSource,
+};
+
+/// High precision event timer.
+///
+/// The main counter runs at a fixed frequency whatever the CPU does, and comparator 0
+/// raises the timer interrupt. Without an I/O APIC, comparator 0 can only reach the PIC
+/// through the legacy replacement route, which takes over IRQ 0 from the PIT and IRQ 8
+/// from the RTC. The RTC polls its interrupt flags meanwhile.
+
+const HPET_CAPABILITIES: usize = 0x000;
+const HPET_CONFIG: usize = 0x010;
+const HPET_INTR_STATUS: usize = 0x020;
+const HPET_MAIN_COUNTER: usize = 0x0f0;
+
+const fn hpet_timer_config(n: usize) -> usize {
+    0x100 + 0x20 * n
+}
+
+const fn hpet_timer_comparator(n: usize) -> usize {
+    0x108 + 0x20 * n
+}
+
+/// Capabilities: the main counter is 64 bits wide.
+const CAP_COUNT_SIZE: usize = 13;
+/// Capabilities: legacy replacement route supported.
+const CAP_LEGACY_ROUTE: usize = 15;
+
+/// General configuration: counter running and legacy replacement route.
+const CONFIG_ENABLE: usize = 0;
+const CONFIG_LEGACY_ROUTE: usize = 1;
+
+/// Timer configuration bits.
+const TIMER_LEVEL_TRIGGERED: usize = 1;
+const TIMER_INT_ENABLE: usize = 2;
+const TIMER_PERIODIC: usize = 3;
+const TIMER_PERIODIC_CAP: usize = 4;
+const TIMER_VAL_SET: usize = 6;
+const TIMER_32BIT_MODE: usize = 8;
+
+/// Femtoseconds per second.
+const FS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+/// Virtual address of the registers, 0 if there is no HPET.
+static HPET_BASE: AtomicUsize = AtomicUsize::new(0);
+/// Main counter period in femtoseconds.
+static HPET_PERIOD_FS: AtomicU32 = AtomicU32::new(0);
+
+/// Software extension of a 32-bit main counter: last value read and wrap count.
+static mut COUNTER_EXTENSION: (u32, u32) = (0, 0);
+
+fn hpet_read(reg: usize) -> u32 {
+    unsafe { read_volatile((HPET_BASE.load(Ordering::Relaxed) + reg) as *const u32) }
+}
+
+fn hpet_write(reg: usize, val: u32) {
+    unsafe { write_volatile((HPET_BASE.load(Ordering::Relaxed) + reg) as *mut u32, val) }
+}
+
+fn capabilities() -> u32 {
+    hpet_read(HPET_CAPABILITIES)
+}
+
+fn is_64bit() -> bool {
+    capabilities().get_bit(CAP_COUNT_SIZE)
+}
+
+/// Reads the 64-bit main counter with 32-bit accesses, retrying when the low half
+/// wrapped between the reads of the high half.
+fn read_counter64() -> u64 {
+    loop {
+        let hi = hpet_read(HPET_MAIN_COUNTER + 4);
+        let lo = hpet_read(HPET_MAIN_COUNTER);
+        if hpet_read(HPET_MAIN_COUNTER + 4) == hi {
+            return (hi as u64) << 32 | lo as u64;
+        }
+    }
+}
+
+/// Reads the main counter, extended to 64 bits in software if it is only 32 bits wide.
+/// A 32-bit counter must then be read at least once per wrap, about 5 minutes.
+fn read_counter() -> u64 {
+    if is_64bit() {
+        return read_counter64();
+    }
+    without_interrupts(|| unsafe {
+        let (last, wraps) = COUNTER_EXTENSION;
+        let cur = hpet_read(HPET_MAIN_COUNTER);
+        let wraps = if cur < last { wraps + 1 } else { wraps };
+        COUNTER_EXTENSION = (cur, wraps);
+        (wraps as u64) << 32 | cur as u64
+    })
+}
+
+pub fn hpet_present() -> bool {
+    HPET_BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Main counter frequency in Hz.
+pub fn frequency() -> u64 {
+    FS_PER_SEC / HPET_PERIOD_FS.load(Ordering::Relaxed).max(1) as u64
+}
+
+/// Finds the HPET through the ACPI HPET table, maps it and starts the main counter.
+/// Returns `false` if there is none.
+pub fn init() -> bool {
+    let Some(table) = acpi::find_table(b"HPET") else {
+        return false;
+    };
+    // Event timer block id, then the address of the registers.
+    let address =
+        unsafe { (table.data().as_ptr().add(4) as *const GenericAddress).read_unaligned() };
+    if address.space_id != ADDRESS_SPACE_MEMORY || address.address >= 1 << 32 {
+        return false;
+    }
+    HPET_BASE.store(
+        mm::map_mmio(address.address as usize, 0x400),
+        Ordering::Relaxed,
+    );
+    HPET_PERIOD_FS.store(hpet_read(HPET_CAPABILITIES + 4), Ordering::Relaxed);
+
+    let mut config = hpet_read(HPET_CONFIG);
+    config.set_bit(CONFIG_ENABLE, false);
+    hpet_write(HPET_CONFIG, config);
+    hpet_write(HPET_MAIN_COUNTER, 0);
+    hpet_write(HPET_MAIN_COUNTER + 4, 0);
+    let mut timer = hpet_read(hpet_timer_config(0));
+    timer.set_bit(TIMER_INT_ENABLE, false);
+    hpet_write(hpet_timer_config(0), timer);
+    config.set_bit(CONFIG_ENABLE, true);
+    hpet_write(HPET_CONFIG, config);
+
+    serial_println!(
+        "HPET: {} Hz, {} bits, {} timers",
+        frequency(),
+        if is_64bit() { 64 } else { 32 },
+        capabilities().get_bits(8..=12) + 1
+    );
+    true
+}
+
+pub struct Hpet;
+
+pub static HPET: Hpet = Hpet;
+
+impl ClockSource for Hpet {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn read(&self) -> u64 {
+        read_counter()
+    }
+
+    fn frequency(&self) -> u64 {
+        frequency()
+    }
+
+    fn rating(&self) -> u32 {
+        250
+    }
+}
+
+impl Hpet {
+    /// Routes comparator 0 to IRQ 0 in edge triggered mode and enables it. A periodic
+    /// comparator runs in 32-bit mode, see `set_periodic`.
+    fn setup_timer0(&self, periodic: bool) {
+        let mut config = hpet_read(HPET_CONFIG);
+        if !config.get_bit(CONFIG_LEGACY_ROUTE) {
+            config.set_bit(CONFIG_LEGACY_ROUTE, true);
+            hpet_write(HPET_CONFIG, config);
+            rtc::set_irq_taken(true);
+        }
+        let mut timer = hpet_read(hpet_timer_config(0));
+        timer.set_bit(TIMER_LEVEL_TRIGGERED, false);
+        timer.set_bit(TIMER_PERIODIC, periodic);
+        timer.set_bit(TIMER_VAL_SET, periodic);
+        timer.set_bit(TIMER_32BIT_MODE, periodic);
+        timer.set_bit(TIMER_INT_ENABLE, true);
+        hpet_write(hpet_timer_config(0), timer);
+        pic_enable_irq(0);
+    }
+
+    fn write_comparator(&self, val: u64) {
+        hpet_write(hpet_timer_comparator(0), val as u32);
+        hpet_write(hpet_timer_comparator(0) + 4, (val >> 32) as u32);
+    }
+}
+
+impl ClockEvent for Hpet {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn oneshot(&self) -> Option<&dyn OneShot> {
+        Some(self)
+    }
+
+    fn can_periodic(&self) -> bool {
+        hpet_read(hpet_timer_config(0)).get_bit(TIMER_PERIODIC_CAP)
+    }
+
+    fn rating(&self) -> u32 {
+        if capabilities().get_bit(CAP_LEGACY_ROUTE) {
+            120
+        } else {
+            0
+        }
+    }
+
+    fn set_periodic(&self, freq: u32) {
+        let period = frequency() / freq as u64;
+        without_interrupts(|| {
+            self.setup_timer0(true);
+            // With the value set bit, the first write sets the comparator and the
+            // second one the period added to it at each interrupt. Each write clears the
+            // bit, so the comparator only takes its low half, hence the 32-bit mode.
+            hpet_write(
+                hpet_timer_comparator(0),
+                (read_counter64() as u32).wrapping_add(period as u32),
+            );
+            hpet_write(hpet_timer_comparator(0), period as u32);
+        })
+    }
+
+    fn shutdown(&self) {
+        let mut timer = hpet_read(hpet_timer_config(0));
+        timer.set_bit(TIMER_INT_ENABLE, false);
+        hpet_write(hpet_timer_config(0), timer);
+        let mut config = hpet_read(HPET_CONFIG);
+        if config.get_bit(CONFIG_LEGACY_ROUTE) {
+            config.set_bit(CONFIG_LEGACY_ROUTE, false);
+            hpet_write(HPET_CONFIG, config);
+            rtc::set_irq_taken(false);
+        }
+    }
+}
+
+impl OneShot for Hpet {
+    fn set_next_event(&self, delta_ns: u64) {
+        let mask = if is_64bit() {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        };
+        let mut delta = (delta_ns.min(self.max_delta_ns()) as u128 * frequency() as u128
+            / 1_000_000_000)
+            .max(1) as u64;
+        without_interrupts(|| {
+            self.setup_timer0(false);
+            // The interrupt only fires when the counter passes the comparator, so a
+            // deadline passing before it is written would be missed: retry further away.
+            loop {
+                let deadline = read_counter64().wrapping_add(delta) & mask;
+                self.write_comparator(deadline);
+                if read_counter64().wrapping_sub(deadline) & mask > mask / 2 {
+                    break;
+                }
+                delta *= 2;
+            }
+        })
+    }
+
+    fn max_delta_ns(&self) -> u64 {
+        // Half a wrap of a 32-bit counter keeps the comparison unambiguous.
+        (u32::MAX as u64 / 2) * 1_000_000_000 / frequency()
+    }
+}
@@ -0,0 +1,424 @@
+#![allow(dead_code)]
+
+use core::{
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    arch::x86::{
+        self,
+        intr::{ExceptionStackFrame, INTR_TABLE},
+        pic::IRQ_VECTOR_BASE,
+        without_interrupts,
+    },
+    io::rtc,
+    irq::{self, work::Work},
+    serial_println, smp,
+    sync::WaitQueue,
+    thread,
+    utils::singleton::Singleton,
+};
+
+use clockevent::{ClockEvent, LAPIC_EVENT, PIT_EVENT};
+use clocksource::{ClockSource, PIT_CLOCK};
+use wheel::TimerWheel;
+
+pub use wheel::TimerId;
+
+pub mod clockevent;
+pub mod clocksource;
+pub mod hpet;
+pub mod tsc;
+pub mod wheel;
+
+/// Frequency of the timer interrupt, in Hz.
+pub const TIMER_FREQ: u32 = 200;
+
+pub const TICK_NS: u64 = 1_000_000_000 / TIMER_FREQ as u64;
+
+/// Ticks processed by the timer wheel.
+static mut TICKS: u64 = 0;
+
+static mut CLOCK_EVENT: &'static dyn ClockEvent = &PIT_EVENT;
+
+const MAX_CLOCK_DEVICES: usize = 4;
+
+/// The clock sources and clock event devices found at boot.
+static mut CLOCKSOURCES: [Option<&'static dyn ClockSource>; MAX_CLOCK_DEVICES] =
+    [None; MAX_CLOCK_DEVICES];
+static mut CLOCK_EVENTS: [Option<&'static dyn ClockEvent>; MAX_CLOCK_DEVICES] =
+    [None; MAX_CLOCK_DEVICES];
+
+/// In one-shot mode, the tick and monotonic time at which the mode was entered.
+static mut ONESHOT_BASE: Option<(u64, u64)> = None;
+
+/// Set while the CPU halts in `idle`.
+static IDLE: AtomicBool = AtomicBool::new(false);
+
+static TIMER_WHEEL: Singleton<TimerWheel> = Singleton::UNINIT;
+
+/// Threads in `sleep_until`.
+static SLEEPERS: WaitQueue = WaitQueue::new();
+
+/// The clock source of the monotonic clock.
+struct MonotonicClock {
+    source: &'static dyn ClockSource,
+    /// Counter value and time at which the source was selected.
+    base_cycles: u64,
+    base_ns: u64,
+    /// Last time returned, the clock never goes backwards when switching sources.
+    last_ns: u64,
+}
+
+static mut CLOCK: MonotonicClock = MonotonicClock {
+    source: &PIT_CLOCK,
+    base_cycles: 0,
+    base_ns: 0,
+    last_ns: 0,
+};
+
+/// Unix time read from the RTC, and the monotonic time at which it was read.
+static mut WALL_CLOCK_BASE: (u64, u64) = (0, 0);
+
+/// Starts the periodic PIT interrupt that drives the clock and the kernel timers,
+/// selects the best clock source and clock event device, and sets the wall clock from
+/// the RTC.
+pub fn init() {
+    INTR_TABLE.get_mut()[IRQ_VECTOR_BASE as usize].set_handle_fn(timer_handler);
+    set_clock_event(&PIT_EVENT);
+    set_clocksource(&PIT_CLOCK);
+
+    register_clocksource(&PIT_CLOCK);
+    if tsc::init() {
+        register_clocksource(&tsc::TSC);
+    }
+    let hpet = hpet::init();
+    if hpet {
+        register_clocksource(&hpet::HPET);
+    }
+    if let Some(best) = unsafe { CLOCKSOURCES }
+        .into_iter()
+        .flatten()
+        .max_by_key(|source| source.rating())
+    {
+        set_clocksource(best);
+    }
+
+    register_clock_event(&PIT_EVENT);
+    // The local APIC timer is calibrated against the clock source, which must not
+    // depend on the tick for that.
+    if !clocksource().needs_tick() && LAPIC_EVENT.init() {
+        register_clock_event(&LAPIC_EVENT);
+    }
+    if hpet {
+        register_clock_event(&hpet::HPET);
+    }
+    if let Some(best) = unsafe { CLOCK_EVENTS }
+        .into_iter()
+        .flatten()
+        .filter(|dev| usable_clock_event(*dev))
+        .max_by_key(|dev| dev.rating())
+    {
+        set_clock_event(best);
+    }
+
+    serial_println!(
+        "clock source: {}, clock event: {} ({})",
+        clocksource().name(),
+        clock_event().name(),
+        if is_tickless() {
+            "one-shot"
+        } else {
+            "periodic"
+        }
+    );
+    rtc::init();
+    set_wall_clock(rtc::read_time().to_unix());
+}
+
+fn register_clocksource(source: &'static dyn ClockSource) {
+    unsafe {
+        let slot = (0..MAX_CLOCK_DEVICES).find(|&i| CLOCKSOURCES[i].is_none());
+        CLOCKSOURCES[slot.expect("too many clock sources")] = Some(source);
+    }
+}
+
+fn register_clock_event(dev: &'static dyn ClockEvent) {
+    unsafe {
+        let slot = (0..MAX_CLOCK_DEVICES).find(|&i| CLOCK_EVENTS[i].is_none());
+        CLOCK_EVENTS[slot.expect("too many clock event devices")] = Some(dev);
+    }
+}
+
+/// Whether DEV can drive the timer with the current clock source.
+fn usable_clock_event(dev: &dyn ClockEvent) -> bool {
+    let oneshot = dev.oneshot().is_some() && !clocksource().needs_tick();
+    dev.rating() > 0 && (dev.can_periodic() || oneshot)
+}
+
+/// Switches the monotonic clock to the clock source named NAME, returns `false` if
+/// there is no such source.
+pub fn select_clocksource(name: &str) -> bool {
+    let Some(source) = unsafe { CLOCKSOURCES }
+        .into_iter()
+        .flatten()
+        .find(|source| source.name() == name)
+    else {
+        return false;
+    };
+    let dev = clock_event();
+    if source.needs_tick() && !dev.can_periodic() {
+        return false;
+    }
+    set_clocksource(source);
+    // Re-evaluates the one-shot mode with the new source.
+    set_clock_event(dev);
+    true
+}
+
+/// Switches the timer interrupt to the clock event device named NAME, returns `false`
+/// if there is no such device or it cannot be used with the current clock source.
+pub fn select_clock_event(name: &str) -> bool {
+    let Some(dev) = unsafe { CLOCK_EVENTS }
+        .into_iter()
+        .flatten()
+        .find(|dev| dev.name() == name && usable_clock_event(*dev))
+    else {
+        return false;
+    };
+    set_clock_event(dev);
+    true
+}
+
+extern "x86-interrupt" fn timer_handler(_f: ExceptionStackFrame) {
+    let Some(_irq) = irq::irq_enter(0) else {
+        return;
+    };
+    clock_event_handler();
+}
+
+/// Handles the interrupt of the clock event device: accounts the elapsed ticks, runs
+/// the expired timers and, in one-shot mode, programs the next event.
+fn clock_event_handler() {
+    let (old, target) = without_interrupts(|| unsafe {
+        match ONESHOT_BASE {
+            Some(_) => (TICKS, current_tick()),
+            None => (TICKS, TICKS + 1),
+        }
+    });
+    run_timers(target);
+    thread::tick(target, target.saturating_sub(old));
+    if is_tickless() {
+        without_interrupts(program_next_event);
+    }
+}
+
+/// Advances the timer wheel up to tick TARGET, running the timers expiring on the way.
+fn run_timers(target: u64) {
+    while without_interrupts(|| unsafe {
+        let wheel = TIMER_WHEEL.get_mut();
+        if wheel.now() < target {
+            TICKS = wheel.advance();
+            true
+        } else {
+            false
+        }
+    }) {
+        while let Some(work) = without_interrupts(|| TIMER_WHEEL.get_mut().pop_expired()) {
+            work.run();
+        }
+    }
+}
+
+/// Switches the timer interrupt to DEV, in one-shot mode when both DEV and the clock
+/// source allow it.
+fn set_clock_event(dev: &'static dyn ClockEvent) {
+    without_interrupts(|| unsafe {
+        let old = CLOCK_EVENT;
+        old.shutdown();
+        CLOCK_EVENT = dev;
+        if dev.oneshot().is_some() && !clocksource().needs_tick() {
+            // The ticks are now derived from the clock source, counting on from here.
+            ONESHOT_BASE = Some((current_tick(), now_ns()));
+            program_next_event();
+        } else {
+            ONESHOT_BASE = None;
+            dev.set_periodic(TIMER_FREQ);
+        }
+    })
+}
+
+pub fn clock_event() -> &'static dyn ClockEvent {
+    without_interrupts(|| unsafe { CLOCK_EVENT })
+}
+
+/// Whether the tick is stopped while idle, the clock event device being one-shot.
+pub fn is_tickless() -> bool {
+    without_interrupts(|| unsafe { ONESHOT_BASE }).is_some()
+}
+
+/// Programs the one-shot clock event for the next tick, or when idle for the next timer
+/// expiry only. Interrupts must be disabled.
+fn program_next_event() {
+    let oneshot = unsafe { ONESHOT_BASE }.zip(clock_event().oneshot());
+    let Some(((base_tick, base_ns), dev)) = oneshot else {
+        return;
+    };
+    let now_tick = current_tick();
+    let next = if IDLE.load(Ordering::Relaxed) {
+        TIMER_WHEEL.next_expiry().unwrap_or(u64::MAX)
+    } else {
+        now_tick + 1
+    }
+    .max(now_tick + 1);
+    let deadline = (next - base_tick)
+        .saturating_mul(TICK_NS)
+        .saturating_add(base_ns);
+    dev.set_next_event(deadline.saturating_sub(now_ns()));
+}
+
+/// Halts the CPU until the next interrupt, it must be called with interrupts enabled.
+///
+/// In one-shot mode the tick is stopped meanwhile, the clock event being programmed for
+/// the next timer expiry instead. Only the bootstrap processor drives the clock, the
+/// other CPUs simply halt.
+pub fn idle() {
+    if !smp::is_bsp() {
+        x86::hlt();
+        return;
+    }
+    x86::cli();
+    IDLE.store(true, Ordering::Relaxed);
+    program_next_event();
+    x86::sti_hlt();
+    x86::cli();
+    IDLE.store(false, Ordering::Relaxed);
+    program_next_event();
+    x86::sti();
+}
+
+fn current_tick() -> u64 {
+    unsafe {
+        match ONESHOT_BASE {
+            Some((tick, ns)) => tick + (now_ns() - ns) / TICK_NS,
+            None => TICKS,
+        }
+    }
+}
+
+/// Ticks since boot.
+pub fn ticks() -> u64 {
+    without_interrupts(current_tick)
+}
+
+/// Switches the monotonic clock to SOURCE.
+fn set_clocksource(source: &'static dyn ClockSource) {
+    without_interrupts(|| {
+        let now = now_ns();
+        unsafe {
+            CLOCK = MonotonicClock {
+                source,
+                base_cycles: source.read(),
+                base_ns: now,
+                last_ns: now,
+            }
+        }
+    })
+}
+
+pub fn clocksource() -> &'static dyn ClockSource {
+    without_interrupts(|| unsafe { CLOCK.source })
+}
+
+/// Monotonic time since boot in nanoseconds, read from the clock source.
+pub fn now_ns() -> u64 {
+    without_interrupts(|| unsafe {
+        let clock = &mut *addr_of_mut!(CLOCK);
+        let cycles = clock.source.read().wrapping_sub(clock.base_cycles);
+        let ns = clock.base_ns
+            + (cycles as u128 * 1_000_000_000 / clock.source.frequency() as u128) as u64;
+        clock.last_ns = clock.last_ns.max(ns);
+        clock.last_ns
+    })
+}
+
+/// Monotonic time since boot.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(now_ns())
+}
+
+/// Time elapsed since the Unix epoch, advanced by the monotonic clock since it was
+/// last set.
+pub fn wall_clock() -> Duration {
+    let (unix, at) = without_interrupts(|| unsafe { WALL_CLOCK_BASE });
+    Duration::from_secs(unix) + Duration::from_nanos(now_ns() - at)
+}
+
+/// Sets the wall clock to UNIX seconds since the epoch.
+pub fn set_wall_clock(unix: u64) {
+    let now = now_ns();
+    without_interrupts(|| unsafe { WALL_CLOCK_BASE = (unix, now) });
+}
+
+/// Converts MS milliseconds to ticks, rounding up.
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TIMER_FREQ as u64).div_ceil(1000)
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_millis(ticks * 1000 / TIMER_FREQ as u64)
+}
+
+/// Sleeps at least MS milliseconds.
+pub fn sleep(ms: u64) {
+    sleep_until(ticks() + ms_to_ticks(ms));
+}
+
+/// Sleeps until tick DEADLINE has passed.
+///
+/// The thread blocks, but before `thread::init` the boot code busy-waits, halting the
+/// CPU between ticks.
+pub fn sleep_until(deadline: u64) {
+    assert!(x86::intr_enabled(), "sleep with interrupts disabled");
+    if thread::current().is_null() {
+        while ticks() < deadline {
+            x86::hlt();
+        }
+    } else {
+        SLEEPERS.wait_event_until(|| false, deadline);
+    }
+}
+
+/// Arms a one-shot timer calling `callback(arg)` in about DELAY_MS milliseconds.
+///
+/// Callbacks run in the timer interrupt handler: they must not sleep and should hand
+/// slow work over to `irq::work::schedule_work`. Returns `None` if no timer is left.
+pub fn add_timer(delay_ms: u64, callback: fn(usize), arg: usize) -> Option<TimerId> {
+    add_timer_at(ticks() + ms_to_ticks(delay_ms), callback, arg)
+}
+
+/// Arms a one-shot timer calling `callback(arg)` at tick DEADLINE.
+pub fn add_timer_at(deadline: u64, callback: fn(usize), arg: usize) -> Option<TimerId> {
+    without_interrupts(|| {
+        TIMER_WHEEL
+            .get_mut()
+            .add(deadline, 0, Work::new(callback, arg))
+    })
+}
+
+/// Arms a timer calling `callback(arg)` every PERIOD_MS milliseconds.
+pub fn add_periodic_timer(period_ms: u64, callback: fn(usize), arg: usize) -> Option<TimerId> {
+    let period = ms_to_ticks(period_ms).max(1);
+    without_interrupts(|| {
+        TIMER_WHEEL
+            .get_mut()
+            .add(ticks() + period, period, Work::new(callback, arg))
+    })
+}
+
+/// Disarms timer ID, returns `false` if it already expired or was cancelled.
+pub fn cancel_timer(id: TimerId) -> bool {
+    without_interrupts(|| TIMER_WHEEL.get_mut().cancel(id))
+}
@@ -0,0 +1,111 @@
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{
+    arch::x86::{
+        self,
+        pic::{pit_channel2_gate, pit_configure_channel, pit_read_counter, pit_reload, PIT_HZ},
+        without_interrupts,
+    },
+    serial_println,
+};
+
+use super::clocksource::ClockSource;
+
+/// Time stamp counter, calibrated against PIT channel 2.
+
+/// Frequency channel 2 runs at during the calibration, each period lasting 20 ms.
+const CALIBRATE_FREQ: u32 = 50;
+const CALIBRATE_ROUNDS: usize = 3;
+
+/// TSC frequency in kHz, 0 until calibrated.
+static TSC_KHZ: AtomicU32 = AtomicU32::new(0);
+
+/// Reads the time stamp counter, for cycle-accurate timestamps and benchmarks.
+pub fn cycles() -> u64 {
+    x86::rdtsc()
+}
+
+/// Waits for the counter of channel 2 to reload, the start of a new period.
+fn wait_channel2_reload() {
+    let mut last = pit_read_counter(2);
+    loop {
+        let cur = pit_read_counter(2);
+        if cur > last {
+            break;
+        }
+        last = cur;
+    }
+}
+
+/// Counts the TSC cycles elapsed during one period of channel 2, keeping the fastest of
+/// a few rounds so that a stall (SMI, emulator hiccup) does not skew the result.
+fn calibrate() -> u64 {
+    without_interrupts(|| {
+        pit_channel2_gate(false);
+        pit_configure_channel(2, 2, CALIBRATE_FREQ);
+        pit_channel2_gate(true);
+        wait_channel2_reload();
+        let mut best = u64::MAX;
+        for _ in 0..CALIBRATE_ROUNDS {
+            let start = x86::rdtsc();
+            wait_channel2_reload();
+            best = best.min(x86::rdtsc() - start);
+        }
+        pit_channel2_gate(false);
+        best * PIT_HZ as u64 / pit_reload(2) as u64
+    })
+}
+
+/// Calibrates the TSC if the CPU has one, returns whether it can be used as clock source.
+pub fn init() -> bool {
+    if !x86::has_tsc() {
+        return false;
+    }
+    let hz = calibrate();
+    TSC_KHZ.store((hz / 1000) as u32, Ordering::Relaxed);
+    serial_println!(
+        "TSC: {}.{:03} MHz{}",
+        hz / 1_000_000,
+        hz / 1000 % 1000,
+        if x86::has_invariant_tsc() {
+            ", invariant"
+        } else {
+            ""
+        }
+    );
+    hz != 0
+}
+
+/// TSC frequency in Hz, 0 if not calibrated.
+pub fn frequency() -> u64 {
+    TSC_KHZ.load(Ordering::Relaxed) as u64 * 1000
+}
+
+pub struct Tsc;
+
+pub static TSC: Tsc = Tsc;
+
+impl ClockSource for Tsc {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn read(&self) -> u64 {
+        x86::rdtsc()
+    }
+
+    fn frequency(&self) -> u64 {
+        frequency()
+    }
+
+    /// A TSC whose rate follows the CPU frequency is worse than the HPET.
+    fn rating(&self) -> u32 {
+        if x86::has_invariant_tsc() {
+            300
+        } else {
+            200
+        }
+    }
+}
@@ -0,0 +1,346 @@
+#![allow(dead_code)]
+
+use alloc::vec::Vec;
+
+use crate::irq::work::Work;
+
+/// Hierarchical timer wheel.
+///
+/// Level 0 has one slot per tick for the next 64 ticks, each higher level has slots 64
+/// times coarser. Whenever the index of a level wraps around, the matching slot of the
+/// level above is cascaded: its timers are re-inserted and fall into the finer levels.
+/// Timers further away than the wheel span are parked in the last slot reachable and
+/// re-inserted from there. Entries are allocated as timers are armed, up to
+/// `MAX_TIMERS`, and reused once free.
+
+const WHEEL_BITS: u32 = 6;
+const WHEEL_SIZE: usize = 1 << WHEEL_BITS;
+const WHEEL_MASK: u64 = WHEEL_SIZE as u64 - 1;
+const WHEEL_LEVELS: usize = 4;
+const WHEEL_SPAN: u64 = 1 << (WHEEL_BITS * WHEEL_LEVELS as u32);
+
+pub const MAX_TIMERS: usize = 1 << 16;
+
+fn slot_index(level: usize, tick: u64) -> usize {
+    ((tick >> (WHEEL_BITS * level as u32)) & WHEEL_MASK) as usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+    index: u16,
+    generation: u16,
+}
+
+#[derive(Clone, Copy)]
+struct TimerEntry {
+    /// `None` while the entry is free.
+    work: Option<Work>,
+    expires: u64,
+    /// Reload interval of a periodic timer, 0 for a one-shot timer.
+    period: u64,
+    generation: u16,
+    prev: Option<u16>,
+    next: Option<u16>,
+    /// Level and slot holding the entry.
+    slot: (u8, u8),
+}
+
+const FREE_ENTRY: TimerEntry = TimerEntry {
+    work: None,
+    expires: 0,
+    period: 0,
+    generation: 0,
+    prev: None,
+    next: None,
+    slot: (0, 0),
+};
+
+pub struct TimerWheel {
+    entries: Vec<TimerEntry>,
+    free: Option<u16>,
+    slots: [[Option<u16>; WHEEL_SIZE]; WHEEL_LEVELS],
+    /// Last processed tick.
+    now: u64,
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            free: None,
+            slots: [[None; WHEEL_SIZE]; WHEEL_LEVELS],
+            now: 0,
+        }
+    }
+}
+
+impl TimerWheel {
+    /// Arms a timer running WORK at tick EXPIRES, then every PERIOD ticks if it is not 0.
+    ///
+    /// A tick already processed is taken as the next one. Returns `None` once
+    /// `MAX_TIMERS` timers are armed.
+    pub fn add(&mut self, expires: u64, period: u64, work: Work) -> Option<TimerId> {
+        if self.free.is_none() {
+            if self.entries.len() == MAX_TIMERS {
+                return None;
+            }
+            self.entries.push(FREE_ENTRY);
+            self.free = Some((self.entries.len() - 1) as u16);
+        }
+        let index = self.free?;
+        let entry = &mut self.entries[index as usize];
+        self.free = entry.next;
+        entry.work = Some(work);
+        entry.expires = expires.max(self.now + 1);
+        entry.period = period;
+        self.insert(index);
+        Some(TimerId {
+            index,
+            generation: self.entries[index as usize].generation,
+        })
+    }
+
+    /// Disarms timer ID, returns `false` if it already expired or was cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        let entry = &self.entries[id.index as usize];
+        if entry.work.is_none() || entry.generation != id.generation {
+            return false;
+        }
+        self.unlink(id.index);
+        self.release(id.index);
+        true
+    }
+
+    /// Tick at which timer ID expires next.
+    pub fn expires(&self, id: TimerId) -> Option<u64> {
+        let entry = &self.entries[id.index as usize];
+        (entry.work.is_some() && entry.generation == id.generation).then_some(entry.expires)
+    }
+
+    /// The earliest expiry among the armed timers, or `None` if none is armed.
+    ///
+    /// Only the first non-empty slot of each level is looked at: the slots of a level
+    /// follow each other in time from its current one, level 0 holding a single tick per
+    /// slot.
+    pub fn next_expiry(&self) -> Option<u64> {
+        (0..WHEEL_LEVELS)
+            .filter_map(|level| {
+                let current = slot_index(level, self.now);
+                // The current slot of a higher level holds timers a whole turn away.
+                let first = (level != 0) as usize;
+                (first..first + WHEEL_SIZE)
+                    .find_map(|offset| self.slots[level][(current + offset) % WHEEL_SIZE])
+            })
+            .map(|head| self.earliest_in_slot(head))
+            .min()
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Advances the wheel by one tick, returns the tick now being processed.
+    ///
+    /// The timers expiring at that tick are then retrieved with `pop_expired`.
+    pub fn advance(&mut self) -> u64 {
+        self.now += 1;
+        let mut level = 0;
+        while level + 1 < WHEEL_LEVELS && slot_index(level, self.now) == 0 {
+            level += 1;
+            self.cascade(level, slot_index(level, self.now));
+        }
+        self.now
+    }
+
+    /// Takes one timer expiring at the current tick, re-arming it if periodic.
+    pub fn pop_expired(&mut self) -> Option<Work> {
+        let slot = slot_index(0, self.now);
+        let index = self.slots[0][slot]?;
+        self.unlink(index);
+        let entry = &mut self.entries[index as usize];
+        let work = entry.work;
+        if entry.period != 0 {
+            entry.expires += entry.period;
+            self.insert(index);
+        } else {
+            self.release(index);
+        }
+        work
+    }
+
+    fn insert(&mut self, index: u16) {
+        let entry = &mut self.entries[index as usize];
+        // A timer cascaded at the tick it expires lands in the slot processed right after.
+        debug_assert!(entry.expires >= self.now);
+        let delta = entry.expires - self.now;
+        let (level, tick) = if delta >= WHEEL_SPAN {
+            (WHEEL_LEVELS - 1, self.now + WHEEL_SPAN - 1)
+        } else {
+            let level = (0..WHEEL_LEVELS)
+                .find(|&level| delta < 1 << (WHEEL_BITS * (level as u32 + 1)))
+                .unwrap();
+            (level, entry.expires)
+        };
+        let slot = slot_index(level, tick);
+        entry.slot = (level as u8, slot as u8);
+        entry.prev = None;
+        entry.next = self.slots[level][slot];
+        if let Some(next) = entry.next {
+            self.entries[next as usize].prev = Some(index);
+        }
+        self.slots[level][slot] = Some(index);
+    }
+
+    fn unlink(&mut self, index: u16) {
+        let TimerEntry {
+            prev, next, slot, ..
+        } = self.entries[index as usize];
+        match prev {
+            Some(prev) => self.entries[prev as usize].next = next,
+            None => self.slots[slot.0 as usize][slot.1 as usize] = next,
+        }
+        if let Some(next) = next {
+            self.entries[next as usize].prev = prev;
+        }
+    }
+
+    fn release(&mut self, index: u16) {
+        let entry = &mut self.entries[index as usize];
+        entry.work = None;
+        entry.generation = entry.generation.wrapping_add(1);
+        entry.prev = None;
+        entry.next = self.free;
+        self.free = Some(index);
+    }
+
+    /// The earliest expiry among the timers of the slot list starting at HEAD.
+    fn earliest_in_slot(&self, head: u16) -> u64 {
+        let mut earliest = u64::MAX;
+        let mut cur = Some(head);
+        while let Some(index) = cur {
+            let entry = &self.entries[index as usize];
+            earliest = earliest.min(entry.expires);
+            cur = entry.next;
+        }
+        earliest
+    }
+
+    fn cascade(&mut self, level: usize, slot: usize) {
+        let mut cur = self.slots[level][slot].take();
+        while let Some(index) = cur {
+            cur = self.entries[index as usize].next;
+            self.insert(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::RefCell;
+    use std::{thread_local, vec::Vec};
+
+    use super::*;
+
+    thread_local! {
+        static FIRED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(arg: usize) {
+        FIRED.with(|fired| fired.borrow_mut().push(arg));
+    }
+
+    fn timer(wheel: &mut TimerWheel, expires: u64, period: u64, arg: usize) -> TimerId {
+        wheel.add(expires, period, Work::new(record, arg)).unwrap()
+    }
+
+    /// Advances WHEEL up to tick UNTIL, returns the argument and tick of each timer run.
+    fn run_until(wheel: &mut TimerWheel, until: u64) -> Vec<(usize, u64)> {
+        let mut fired = Vec::new();
+        while wheel.now() < until {
+            let tick = wheel.advance();
+            while let Some(work) = wheel.pop_expired() {
+                work.run();
+                let arg = FIRED.with(|fired| fired.borrow_mut().pop()).unwrap();
+                fired.push((arg, tick));
+            }
+        }
+        fired
+    }
+
+    #[test]
+    fn timers_fire_at_their_tick_on_every_level() {
+        let mut wheel = TimerWheel::default();
+        // Levels 0, 1, 2 and 3, then beyond the wheel span.
+        let ticks = [5, 200, 10_000, 300_000, WHEEL_SPAN + 70];
+        for (arg, &tick) in ticks.iter().enumerate().rev() {
+            timer(&mut wheel, tick, 0, arg);
+        }
+        let fired = run_until(&mut wheel, WHEEL_SPAN + 100);
+        let expected: Vec<_> = ticks.iter().copied().enumerate().collect();
+        assert_eq!(fired, expected);
+        assert_eq!(wheel.next_expiry(), None);
+    }
+
+    #[test]
+    fn past_ticks_fire_on_the_next_one() {
+        let mut wheel = TimerWheel::default();
+        run_until(&mut wheel, 10);
+        timer(&mut wheel, 3, 0, 1);
+        assert_eq!(wheel.next_expiry(), Some(11));
+        assert_eq!(run_until(&mut wheel, 20), [(1, 11)]);
+    }
+
+    #[test]
+    fn periodic_timer_is_rearmed() {
+        let mut wheel = TimerWheel::default();
+        let id = timer(&mut wheel, 10, 30, 7);
+        assert_eq!(run_until(&mut wheel, 75), [(7, 10), (7, 40), (7, 70)]);
+        assert_eq!(wheel.expires(id), Some(100));
+    }
+
+    #[test]
+    fn cancelled_timer_does_not_fire() {
+        let mut wheel = TimerWheel::default();
+        let id = timer(&mut wheel, 100, 0, 1);
+        timer(&mut wheel, 150, 0, 2);
+        assert!(wheel.cancel(id));
+        assert!(!wheel.cancel(id));
+        assert_eq!(wheel.expires(id), None);
+        assert_eq!(run_until(&mut wheel, 200), [(2, 150)]);
+    }
+
+    #[test]
+    fn reused_entry_does_not_answer_to_old_id() {
+        let mut wheel = TimerWheel::default();
+        let old = timer(&mut wheel, 10, 0, 1);
+        assert!(wheel.cancel(old));
+        let new = timer(&mut wheel, 20, 0, 2);
+        assert_eq!(wheel.entries.len(), 1);
+        assert!(!wheel.cancel(old));
+        assert_eq!(wheel.expires(new), Some(20));
+    }
+
+    #[test]
+    fn next_expiry_compares_levels() {
+        let mut wheel = TimerWheel::default();
+        // Armed on level 1, then a later timer lands on level 0 as time goes by.
+        timer(&mut wheel, 100, 0, 1);
+        run_until(&mut wheel, 50);
+        timer(&mut wheel, 113, 0, 2);
+        assert_eq!(wheel.next_expiry(), Some(100));
+        timer(&mut wheel, 60, 0, 3);
+        assert_eq!(wheel.next_expiry(), Some(60));
+    }
+
+    #[test]
+    fn add_fails_once_every_entry_is_armed() {
+        let mut wheel = TimerWheel::default();
+        for i in 0..MAX_TIMERS {
+            timer(&mut wheel, 1 + i as u64, 0, i);
+        }
+        assert!(wheel.add(1, 0, Work::new(record, 0)).is_none());
+        assert_eq!(wheel.next_expiry(), Some(1));
+    }
+}
@@ -0,0 +1,383 @@
+#![allow(dead_code)]
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{mem::size_of, ptr};
+
+use crate::mm::{
+    addr_space::AddressSpace,
+    page::{PAGE_SIZE, PG_RW, PG_USER},
+};
+
+use super::{enter_user, process, vdso, Errno};
+
+/// ELF32 executables.
+///
+/// `exec` runs a statically linked i386 executable in the current thread. The `PT_LOAD`
+/// segments are mapped into a fresh address space, writable only if the segment is, the
+/// rest of the last page and the part beyond the file size zeroed. The stack ends below
+/// the vDSO and starts as the System V ABI wants it: `argc`, the `argv` and `envp`
+/// pointers each ended by a null one, then the auxiliary vector, whose `AT_SYSINFO`
+/// entry gives the system call entry of the vDSO.
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PF_W: u32 = 2;
+
+const AT_NULL: u32 = 0;
+const AT_PHDR: u32 = 3;
+const AT_PHENT: u32 = 4;
+const AT_PHNUM: u32 = 5;
+const AT_PAGESZ: u32 = 6;
+const AT_ENTRY: u32 = 9;
+const AT_SYSINFO: u32 = 32;
+
+/// Top of the user stack, right below the vDSO.
+pub const USER_STACK_TOP: usize = vdso::VDSO_ADDR;
+pub const USER_STACK_SIZE: usize = 64 * 1024;
+const USER_STACK_BOTTOM: usize = USER_STACK_TOP - USER_STACK_SIZE;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ElfHeader {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u32,
+    phoff: u32,
+    shoff: u32,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ProgramHeader {
+    kind: u32,
+    offset: u32,
+    vaddr: u32,
+    paddr: u32,
+    filesz: u32,
+    memsz: u32,
+    flags: u32,
+    align: u32,
+}
+
+/// Why an executable cannot be run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// Not an ELF file.
+    BadMagic,
+    /// Not a little endian ELF32 i386 executable.
+    Unsupported,
+    /// A header lies outside the file or has a bad size.
+    BadHeader,
+    /// A segment lies outside the file or the user part of the address space.
+    BadSegment,
+    /// Dynamically linked.
+    Interpreter,
+    /// The arguments and environment do not fit in the stack.
+    ArgumentsTooLong,
+    OutOfMemory,
+}
+
+impl From<ElfError> for Errno {
+    fn from(err: ElfError) -> Self {
+        match err {
+            ElfError::ArgumentsTooLong => Errno::E2BIG,
+            ElfError::OutOfMemory => Errno::ENOMEM,
+            _ => Errno::ENOEXEC,
+        }
+    }
+}
+
+/// Reads a T at OFFSET in DATA.
+fn read<T: Copy>(data: &[u8], offset: usize) -> Result<T, ElfError> {
+    match offset.checked_add(size_of::<T>()) {
+        Some(end) if end <= data.len() => {
+            Ok(unsafe { ptr::read_unaligned(data[offset..].as_ptr() as *const T) })
+        }
+        _ => Err(ElfError::BadHeader),
+    }
+}
+
+/// An executable loaded in an address space.
+pub struct Image {
+    pub space: AddressSpace,
+    pub entry: usize,
+    /// Where the program headers are mapped, if a segment covers them.
+    phdr: Option<usize>,
+    phnum: usize,
+}
+
+/// Validates the executable in DATA and loads it in a fresh address space.
+pub fn load(data: &[u8]) -> Result<Image, ElfError> {
+    let header: ElfHeader = read(data, 0)?;
+    if header.ident[..4] != ELF_MAGIC {
+        return Err(ElfError::BadMagic);
+    }
+    if header.ident[4] != ELFCLASS32
+        || header.ident[5] != ELFDATA2LSB
+        || header.ident[6] != EV_CURRENT
+        || header.kind != ET_EXEC
+        || header.machine != EM_386
+    {
+        return Err(ElfError::Unsupported);
+    }
+    if header.phentsize as usize != size_of::<ProgramHeader>() {
+        return Err(ElfError::BadHeader);
+    }
+    let phdrs = (0..header.phnum as usize)
+        .map(|i| {
+            let offset = (header.phoff as usize)
+                .checked_add(i * size_of::<ProgramHeader>())
+                .ok_or(ElfError::BadHeader)?;
+            read::<ProgramHeader>(data, offset)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if phdrs.iter().any(|ph| ph.kind == PT_INTERP) {
+        return Err(ElfError::Interpreter);
+    }
+
+    let mut space = AddressSpace::new().ok_or(ElfError::OutOfMemory)?;
+    let mut phdr = None;
+    for ph in phdrs.iter().filter(|ph| ph.kind == PT_LOAD) {
+        let (vaddr, memsz) = (ph.vaddr as usize, ph.memsz as usize);
+        let (offset, filesz) = (ph.offset as usize, ph.filesz as usize);
+        let end = vaddr.checked_add(memsz).ok_or(ElfError::BadSegment)?;
+        if filesz > memsz
+            || end > USER_STACK_BOTTOM
+            || offset
+                .checked_add(filesz)
+                .is_none_or(|end| end > data.len())
+            || vaddr % PAGE_SIZE != offset % PAGE_SIZE
+        {
+            return Err(ElfError::BadSegment);
+        }
+        let flags = if ph.flags & PF_W != 0 {
+            PG_USER | PG_RW
+        } else {
+            PG_USER
+        };
+        for page in (vaddr & !(PAGE_SIZE - 1)..end).step_by(PAGE_SIZE) {
+            space.map_new(page, flags).ok_or(ElfError::OutOfMemory)?;
+        }
+        space.write(vaddr, &data[offset..offset + filesz]);
+        let phoff = header.phoff as usize;
+        if offset <= phoff && phoff - offset < filesz {
+            phdr = Some(vaddr + phoff - offset);
+        }
+    }
+    Ok(Image {
+        space,
+        entry: header.entry as usize,
+        phdr,
+        phnum: header.phnum as usize,
+    })
+}
+
+/// Maps the stack and the vDSO of IMAGE and fills the stack with ARGV, ENVP and the
+/// auxiliary vector. Returns the initial stack pointer.
+fn setup_stack(image: &mut Image, argv: &[String], envp: &[String]) -> Result<usize, ElfError> {
+    let space = &mut image.space;
+    for page in (USER_STACK_BOTTOM..USER_STACK_TOP).step_by(PAGE_SIZE) {
+        space
+            .map_new(page, PG_USER | PG_RW)
+            .ok_or(ElfError::OutOfMemory)?;
+    }
+    if !space.map_shared(vdso::VDSO_ADDR, vdso::frame(), PG_USER) {
+        return Err(ElfError::OutOfMemory);
+    }
+
+    // The strings go at the top, each followed by a null byte.
+    let mut top = USER_STACK_TOP;
+    let mut push_str = |s: &str| {
+        let start = top
+            .checked_sub(s.len() + 1)
+            .filter(|&start| start >= USER_STACK_BOTTOM)
+            .ok_or(ElfError::ArgumentsTooLong)?;
+        if !space.write(start, s.as_bytes()) || !space.write(start + s.len(), &[0]) {
+            return Err(ElfError::ArgumentsTooLong);
+        }
+        top = start;
+        Ok(start as u32)
+    };
+    let argv_ptrs = argv
+        .iter()
+        .map(|s| push_str(s))
+        .collect::<Result<Vec<_>, _>>()?;
+    let envp_ptrs = envp
+        .iter()
+        .map(|s| push_str(s))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut words = Vec::new();
+    words.push(argv.len() as u32);
+    words.extend_from_slice(&argv_ptrs);
+    words.push(0);
+    words.extend_from_slice(&envp_ptrs);
+    words.push(0);
+    if let Some(phdr) = image.phdr {
+        words.extend_from_slice(&[AT_PHDR, phdr as u32]);
+    }
+    words.extend_from_slice(&[
+        AT_PHENT,
+        size_of::<ProgramHeader>() as u32,
+        AT_PHNUM,
+        image.phnum as u32,
+        AT_PAGESZ,
+        PAGE_SIZE as u32,
+        AT_ENTRY,
+        image.entry as u32,
+        AT_SYSINFO,
+        vdso::VDSO_ADDR as u32,
+        AT_NULL,
+        0,
+    ]);
+    let size = words.len() * size_of::<u32>();
+    let sp = top
+        .checked_sub(size)
+        .map(|sp| sp & !0xf)
+        .filter(|&sp| sp >= USER_STACK_BOTTOM)
+        .ok_or(ElfError::ArgumentsTooLong)?;
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    if !image.space.write(sp, &bytes) {
+        return Err(ElfError::ArgumentsTooLong);
+    }
+    Ok(sp)
+}
+
+/// Loads the executable in DATA and sets up its stack for the arguments ARGV and the
+/// environment ENVP. Returns the image and the initial stack pointer.
+pub fn prepare(data: &[u8], argv: &[String], envp: &[String]) -> Result<(Image, usize), ElfError> {
+    let mut image = load(data)?;
+    let sp = setup_stack(&mut image, argv, envp)?;
+    Ok((image, sp))
+}
+
+/// Replaces the ring 3 part of the current thread, and of its process, with the
+/// executable in DATA, run with the arguments ARGV and the environment ENVP. Returns
+/// only on failure, leaving the thread as it was. The kernel frames of the callers are
+/// lost on success, so ARGV and ENVP are taken to be freed here.
+pub fn exec(data: &[u8], argv: Vec<String>, envp: Vec<String>) -> ElfError {
+    let (image, sp) = match prepare(data, &argv, &envp) {
+        Ok(prepared) => prepared,
+        Err(err) => return err,
+    };
+    drop((argv, envp));
+    process::replace_address_space(Arc::new(image.space));
+    enter_user(image.entry, sp)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::slice;
+
+    use super::*;
+
+    /// Header of an i386 executable whose PHNUM program headers follow it.
+    fn header(phnum: u16) -> ElfHeader {
+        let mut ident = [0; 16];
+        ident[..4].copy_from_slice(&ELF_MAGIC);
+        ident[4] = ELFCLASS32;
+        ident[5] = ELFDATA2LSB;
+        ident[6] = EV_CURRENT;
+        ElfHeader {
+            ident,
+            kind: ET_EXEC,
+            machine: EM_386,
+            version: 1,
+            entry: 0x0804_8000,
+            phoff: size_of::<ElfHeader>() as u32,
+            shoff: 0,
+            flags: 0,
+            ehsize: size_of::<ElfHeader>() as u16,
+            phentsize: size_of::<ProgramHeader>() as u16,
+            phnum,
+            shentsize: 0,
+            shnum: 0,
+            shstrndx: 0,
+        }
+    }
+
+    fn file(header: &ElfHeader, phdrs: &[ProgramHeader]) -> Vec<u8> {
+        let bytes = |ptr: *const u8, len| unsafe { slice::from_raw_parts(ptr, len) };
+        let mut data = bytes(header as *const _ as *const u8, size_of::<ElfHeader>()).to_vec();
+        for ph in phdrs {
+            data.extend_from_slice(bytes(
+                ph as *const _ as *const u8,
+                size_of::<ProgramHeader>(),
+            ));
+        }
+        data
+    }
+
+    fn load_error(data: &[u8]) -> Option<ElfError> {
+        load(data).err()
+    }
+
+    #[test]
+    fn truncated_header_is_rejected() {
+        let data = file(&header(0), &[]);
+        assert_eq!(load_error(&[]), Some(ElfError::BadHeader));
+        assert_eq!(load_error(&data[..20]), Some(ElfError::BadHeader));
+    }
+
+    #[test]
+    fn other_formats_are_rejected() {
+        let mut elf = header(0);
+        elf.ident[0] = b'M';
+        assert_eq!(load_error(&file(&elf, &[])), Some(ElfError::BadMagic));
+        let mut elf64 = header(0);
+        elf64.ident[4] = 2;
+        assert_eq!(load_error(&file(&elf64, &[])), Some(ElfError::Unsupported));
+        let mut x86_64 = header(0);
+        x86_64.machine = 62;
+        assert_eq!(load_error(&file(&x86_64, &[])), Some(ElfError::Unsupported));
+        let mut shared = header(0);
+        shared.kind = 3;
+        assert_eq!(load_error(&file(&shared, &[])), Some(ElfError::Unsupported));
+    }
+
+    #[test]
+    fn program_headers_outside_the_file_are_rejected() {
+        assert_eq!(
+            load_error(&file(&header(2), &[])),
+            Some(ElfError::BadHeader)
+        );
+        let mut far = header(2);
+        far.phoff = u32::MAX;
+        assert_eq!(load_error(&file(&far, &[])), Some(ElfError::BadHeader));
+        let mut wide = header(0);
+        wide.phentsize += 4;
+        assert_eq!(load_error(&file(&wide, &[])), Some(ElfError::BadHeader));
+    }
+
+    #[test]
+    fn dynamically_linked_executables_are_rejected() {
+        let interp = ProgramHeader {
+            kind: PT_INTERP,
+            offset: 0,
+            vaddr: 0,
+            paddr: 0,
+            filesz: 0,
+            memsz: 0,
+            flags: 0,
+            align: 1,
+        };
+        let data = file(&header(1), &[interp]);
+        assert_eq!(load_error(&data), Some(ElfError::Interpreter));
+    }
+}
@@ -0,0 +1,42 @@
+#![allow(dead_code)]
+
+/// Error codes of the system calls, numbered as on Linux. A failed call returns the
+/// negated code in EAX.
+#[allow(clippy::upper_case_acronyms)] // The POSIX names, as user programs know them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Errno {
+    /// Operation not permitted.
+    EPERM = 1,
+    /// No such file or directory.
+    ENOENT = 2,
+    /// No such process.
+    ESRCH = 3,
+    /// Interrupted system call.
+    EINTR = 4,
+    /// Argument list too long.
+    E2BIG = 7,
+    /// Exec format error.
+    ENOEXEC = 8,
+    /// Bad file descriptor.
+    EBADF = 9,
+    /// No child processes.
+    ECHILD = 10,
+    /// Resource temporarily unavailable.
+    EAGAIN = 11,
+    /// Out of memory.
+    ENOMEM = 12,
+    /// Bad address.
+    EFAULT = 14,
+    /// Invalid argument.
+    EINVAL = 22,
+    /// Function not implemented.
+    ENOSYS = 38,
+}
+
+impl Errno {
+    /// The value a failed system call returns in EAX.
+    pub fn to_return(self) -> u32 {
+        (self as u32).wrapping_neg()
+    }
+}
@@ -0,0 +1,87 @@
+#![allow(dead_code)]
+
+use alloc::{string::String, vec::Vec};
+
+use crate::print;
+
+use super::Errno;
+
+/// File descriptor tables.
+///
+/// A descriptor is an index in the table of its process, closed descriptors leave a
+/// hole that the next `install` fills, lowest first as POSIX wants. The console is the
+/// only file for now, opened as the standard input, output and error of every process.
+
+pub const STDIN: u32 = 0;
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+/// An open file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum File {
+    /// Output to the screen, there is no input yet.
+    Console,
+}
+
+impl File {
+    /// Writes BYTES, returns how many were written.
+    pub fn write(&self, bytes: &[u8]) -> Result<usize, Errno> {
+        match self {
+            File::Console => {
+                print!("{}", String::from_utf8_lossy(bytes));
+                Ok(bytes.len())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FdTable {
+    files: Vec<Option<File>>,
+}
+
+impl FdTable {
+    /// A table with the console as descriptors 0, 1 and 2.
+    pub fn with_console() -> Self {
+        Self {
+            files: [Some(File::Console); 3].to_vec(),
+        }
+    }
+
+    /// The file open as FD.
+    pub fn get(&self, fd: u32) -> Result<File, Errno> {
+        match self.files.get(fd as usize) {
+            Some(Some(file)) => Ok(*file),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// Opens FILE as the lowest free descriptor, which is returned.
+    pub fn install(&mut self, file: File) -> u32 {
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                fd as u32
+            }
+            None => {
+                self.files.push(Some(file));
+                self.files.len() as u32 - 1
+            }
+        }
+    }
+
+    pub fn close(&mut self, fd: u32) -> Result<(), Errno> {
+        match self.files.get_mut(fd as usize) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// Closes every descriptor.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
@@ -0,0 +1,244 @@
+#![allow(dead_code)]
+
+use core::arch::asm;
+
+use crate::{
+    arch::x86::{
+        self,
+        gdt::{self, USER_CODE_SELECTOR, USER_DATA_SELECTOR},
+        intr::{ExceptionStackFrame, INTR_TABLE},
+    },
+    irq::{
+        self,
+        level::{self, Irql},
+    },
+    loader::KERNEL_VADDR_BASE,
+    mm::page::{self, PAGE_SIZE},
+    println, serial_println, thread,
+};
+
+pub use errno::Errno;
+
+pub mod elf;
+pub mod errno;
+pub mod fd;
+pub mod process;
+pub mod syscall;
+pub mod vdso;
+
+/// User mode.
+///
+/// A kernel thread drops to ring 3 with `enter_user` and never returns from it, it comes
+/// back to the kernel only through interrupts and exceptions, on the top of its kernel
+/// stack given by the TSS. A fault in ring 3 kills the thread instead of the kernel: it
+/// is reported over serial and on the console, and the thread exits with
+/// `FAULT_EXIT_BASE` plus the vector of the fault, which its joiner, or the parent of
+/// its process, gets back. Ring 3 asks the kernel for services through the system calls
+/// of `syscall`, which check the user memory they are given with `user_slice`.
+
+/// Exit code of a thread killed by a fault in ring 3, before the vector is added.
+pub const FAULT_EXIT_BASE: usize = 128;
+
+/// EFLAGS of a thread entering ring 3: interrupts enabled, the reserved bit 1 set.
+const USER_EFLAGS: u32 = 0x202;
+
+/// Fault raised by code running in ring 3.
+#[derive(Debug, Clone, Copy)]
+pub enum UserFault {
+    DivideError,
+    Overflow,
+    BoundRangeExceeded,
+    InvalidOpcode,
+    SegmentNotPresent { error_code: u32 },
+    StackSegment { error_code: u32 },
+    GeneralProtection { error_code: u32 },
+    PageFault { addr: u32, error_code: u32 },
+    X87FloatingPoint,
+    AlignmentCheck,
+    SimdFloatingPoint,
+}
+
+impl UserFault {
+    pub fn vector(&self) -> u8 {
+        match self {
+            UserFault::DivideError => 0,
+            UserFault::Overflow => 4,
+            UserFault::BoundRangeExceeded => 5,
+            UserFault::InvalidOpcode => 6,
+            UserFault::SegmentNotPresent { .. } => 11,
+            UserFault::StackSegment { .. } => 12,
+            UserFault::GeneralProtection { .. } => 13,
+            UserFault::PageFault { .. } => 14,
+            UserFault::X87FloatingPoint => 16,
+            UserFault::AlignmentCheck => 17,
+            UserFault::SimdFloatingPoint => 19,
+        }
+    }
+}
+
+/// Drops the current thread to ring 3 at ENTRY with the stack pointer STACK, both in
+/// pages mapped with `PG_USER`. Interrupts must be enabled and the thread must have a
+/// kernel stack: the kernel frames of the caller are lost, the next interrupt starts
+/// over at the top of the stack.
+pub fn enter_user(entry: usize, stack: usize) -> ! {
+    assert!(
+        x86::intr_enabled() && level::current_level() == Irql::PASSIVE && !irq::in_interrupt(),
+        "entering ring 3 in atomic context"
+    );
+    unsafe {
+        // The kernel lock is not held, the interrupts are disabled by hand until iret.
+        asm!(
+            "cli",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "push {data:e}",
+            "push {stack:e}",
+            "push {eflags}",
+            "push {code}",
+            "push {entry:e}",
+            "iretd",
+            data = in(reg) USER_DATA_SELECTOR.0 as u32,
+            stack = in(reg) stack as u32,
+            entry = in(reg) entry as u32,
+            eflags = const USER_EFLAGS,
+            code = const USER_CODE_SELECTOR.0,
+            options(noreturn)
+        )
+    }
+}
+
+/// Makes the interrupts and system calls from ring 3 land at TOP, the top of the kernel
+/// stack of the thread switched to on the running CPU. Interrupts must be disabled.
+pub(crate) fn set_kernel_stack(top: usize) {
+    gdt::set_kernel_stack(top as u32);
+    syscall::set_sysenter_stack(top);
+}
+
+/// Checks that the LEN bytes at ADDR are mapped for ring 3 in the current address
+/// space, and writable if WRITE.
+fn check_user_range(addr: usize, len: usize, write: bool) -> Result<(), Errno> {
+    let end = addr.checked_add(len).ok_or(Errno::EFAULT)?;
+    if end > KERNEL_VADDR_BASE as usize {
+        return Err(Errno::EFAULT);
+    }
+    let dir = page::current_page_dir();
+    let mut vaddr = addr & !(PAGE_SIZE - 1);
+    while vaddr < end {
+        match page::page_entry(dir, vaddr) {
+            Some(pte) if pte.is_user() && (pte.rw() || !write) => {}
+            _ => return Err(Errno::EFAULT),
+        }
+        vaddr += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+/// The LEN bytes of user memory at ADDR, if ring 3 may read them.
+pub fn user_slice<'a>(addr: usize, len: usize) -> Result<&'a [u8], Errno> {
+    check_user_range(addr, len, false)?;
+    Ok(unsafe { core::slice::from_raw_parts(addr as *const u8, len) })
+}
+
+/// The LEN bytes of user memory at ADDR, if ring 3 may write them.
+pub fn user_slice_mut<'a>(addr: usize, len: usize) -> Result<&'a mut [u8], Errno> {
+    check_user_range(addr, len, true)?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) })
+}
+
+/// Reports FAULT, raised in ring 3 at FRAME, and kills the current thread.
+pub fn fault(fault: UserFault, frame: &ExceptionStackFrame) -> ! {
+    let tid = unsafe { (*thread::current()).tid() };
+    serial_println!(
+        "user: thread {} killed by {:?} at {:#x}",
+        tid,
+        fault,
+        frame.instruction_pointer
+    );
+    println!(
+        "thread {} killed by {:?} at {:#x}",
+        tid, fault, frame.instruction_pointer
+    );
+    // The gate disabled the interrupts, the kernel lock was not taken.
+    x86::sti();
+    thread::exit(FAULT_EXIT_BASE + fault.vector() as usize)
+}
+
+/// Kills the current thread if FAULT comes from ring 3, panics otherwise.
+fn check(fault: UserFault, frame: &ExceptionStackFrame) {
+    if frame.is_user_mode() {
+        self::fault(fault, frame);
+    }
+    panic!("{:?} in the kernel: {:?}", fault, frame);
+}
+
+/// Installs the handlers of the faults ring 3 code may raise, other than the page fault
+/// and segment not present ones of `main`, the system call entries and the vDSO.
+pub fn init() {
+    page::protect_kernel();
+    let table = INTR_TABLE.get_mut();
+    table.divide_error.set_handle_fn(divide_error_handler);
+    table.overflow.set_handle_fn(overflow_handler);
+    table
+        .bound_range_exceeded
+        .set_handle_fn(bound_range_exceeded_handler);
+    table.invalid_opcode.set_handle_fn(invalid_opcode_handler);
+    table
+        .stack_segment_fault
+        .set_handle_fn(stack_segment_fault_handler);
+    table
+        .general_protection_fault
+        .set_handle_fn(general_protection_fault_handler);
+    table
+        .x87_floating_point
+        .set_handle_fn(x87_floating_point_handler);
+    table.alignment_check.set_handle_fn(alignment_check_handler);
+    table
+        .simd_floating_point
+        .set_handle_fn(simd_floating_point_handler);
+    syscall::init();
+    vdso::init();
+}
+
+/// Sets up the system call entries of an application processor.
+pub(crate) fn init_ap() {
+    syscall::init_cpu();
+}
+
+extern "x86-interrupt" fn divide_error_handler(f: ExceptionStackFrame) {
+    check(UserFault::DivideError, &f);
+}
+
+extern "x86-interrupt" fn overflow_handler(f: ExceptionStackFrame) {
+    check(UserFault::Overflow, &f);
+}
+
+extern "x86-interrupt" fn bound_range_exceeded_handler(f: ExceptionStackFrame) {
+    check(UserFault::BoundRangeExceeded, &f);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(f: ExceptionStackFrame) {
+    check(UserFault::InvalidOpcode, &f);
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler(f: ExceptionStackFrame, error_code: u32) {
+    check(UserFault::StackSegment { error_code }, &f);
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    f: ExceptionStackFrame,
+    error_code: u32,
+) {
+    check(UserFault::GeneralProtection { error_code }, &f);
+}
+
+extern "x86-interrupt" fn x87_floating_point_handler(f: ExceptionStackFrame) {
+    check(UserFault::X87FloatingPoint, &f);
+}
+
+extern "x86-interrupt" fn alignment_check_handler(f: ExceptionStackFrame, _error_code: u32) {
+    check(UserFault::AlignmentCheck, &f);
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler(f: ExceptionStackFrame) {
+    check(UserFault::SimdFloatingPoint, &f);
+}
@@ -0,0 +1,296 @@
+#![allow(dead_code)]
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::mem;
+
+use crate::{
+    arch::x86::without_interrupts, mm::addr_space::AddressSpace, println, sync::WaitQueue, thread,
+    utils::singleton::Singleton,
+};
+
+use super::{
+    elf, enter_user,
+    fd::{FdTable, File, STDERR},
+    Errno,
+};
+
+/// Processes.
+///
+/// A process owns an address space, a file descriptor table and the threads running in
+/// them, which carry its PID. When its last thread exits, the process frees its address
+/// space and files and stays in the table as a zombie holding its exit code, until its
+/// parent reaps it with `waitpid`. The children of an exiting process are handed to
+/// process 1, init, whose kernel thread reaps them and reports their exit.
+///
+/// Processes started by kernel threads, which belong to no process, are children of
+/// init as well.
+
+/// PID of init.
+pub const INIT_PID: u32 = 1;
+
+/// PIDs are reused, from 1 to `PID_MAX`.
+const PID_MAX: u32 = 32768;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    /// Exited with the code, waiting for its parent to reap it.
+    Zombie(usize),
+}
+
+pub struct Process {
+    pid: u32,
+    parent: u32,
+    children: Vec<u32>,
+    /// Identifiers of the live threads.
+    threads: Vec<u32>,
+    /// `None` for init and once the process exited.
+    space: Option<Arc<AddressSpace>>,
+    files: FdTable,
+    /// Code given to `exit`, the last thread gives it otherwise.
+    exit_code: Option<usize>,
+    state: ProcessState,
+}
+
+impl Process {
+    fn new(pid: u32, parent: u32, space: Option<Arc<AddressSpace>>) -> Self {
+        Self {
+            pid,
+            parent,
+            children: Vec::new(),
+            threads: Vec::new(),
+            space,
+            files: FdTable::with_console(),
+            exit_code: None,
+            state: ProcessState::Running,
+        }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn parent(&self) -> u32 {
+        self.parent
+    }
+
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+}
+
+/// Every process, running or zombie, by PID. Only touched with interrupts disabled.
+#[derive(Default)]
+struct ProcessTable {
+    /// Last PID allocated.
+    last_pid: u32,
+    procs: BTreeMap<u32, Process>,
+}
+
+impl ProcessTable {
+    /// The PID following the last one allocated that no process uses, `None` if all
+    /// are taken.
+    fn alloc_pid(&mut self) -> Option<u32> {
+        for _ in 0..PID_MAX {
+            self.last_pid = self.last_pid % PID_MAX + 1;
+            if !self.procs.contains_key(&self.last_pid) {
+                return Some(self.last_pid);
+            }
+        }
+        None
+    }
+
+    fn get(&mut self, pid: u32) -> &mut Process {
+        self.procs.get_mut(&pid).expect("no such process")
+    }
+}
+
+static PROCESSES: Singleton<ProcessTable> = Singleton::UNINIT;
+
+/// Woken whenever a process becomes a zombie, the waiters look for their children.
+static CHILD_EXITED: WaitQueue = WaitQueue::new();
+
+/// PID of the process of the current thread, 0 for a kernel thread.
+pub fn current_pid() -> u32 {
+    unsafe { (*thread::current()).pid() }
+}
+
+/// PID of the parent of the current process, 0 for a kernel thread.
+pub fn parent_pid() -> u32 {
+    match current_pid() {
+        0 => 0,
+        pid => without_interrupts(|| PROCESSES.get_mut().get(pid).parent),
+    }
+}
+
+/// Starts the executable in DATA with the arguments ARGV and the environment ENVP in a
+/// new process, child of the current one. Returns its PID.
+pub fn spawn(data: &[u8], argv: &[String], envp: &[String]) -> Result<u32, Errno> {
+    let (image, stack) = elf::prepare(data, argv, envp)?;
+    let entry = image.entry;
+    let space = Arc::new(image.space);
+    let parent = match current_pid() {
+        0 => INIT_PID,
+        pid => pid,
+    };
+    let pid = without_interrupts(|| {
+        let table = PROCESSES.get_mut();
+        let pid = table.alloc_pid()?;
+        table
+            .procs
+            .insert(pid, Process::new(pid, parent, Some(space)));
+        table.get(parent).children.push(pid);
+        Some(pid)
+    })
+    .ok_or(Errno::EAGAIN)?;
+    let start = Box::new((pid, entry, stack));
+    thread::spawn(process_start, Box::into_raw(start) as usize);
+    Ok(pid)
+}
+
+/// Adds the current thread to process PID, returns the address space of the latter.
+fn join(pid: u32) -> Option<Arc<AddressSpace>> {
+    without_interrupts(|| {
+        let process = PROCESSES.get_mut().get(pid);
+        process.threads.push(unsafe { (*thread::current()).tid() });
+        thread::set_pid(pid);
+        process.space.clone()
+    })
+}
+
+/// First thread of a process, ARG is the boxed PID, entry point and stack pointer.
+fn process_start(arg: usize) -> usize {
+    let (pid, entry, stack) = *unsafe { Box::from_raw(arg as *mut (u32, usize, usize)) };
+    thread::set_address_space(join(pid));
+    enter_user(entry, stack)
+}
+
+/// Makes SPACE the address space of the current thread and of its process, if any.
+pub fn replace_address_space(space: Arc<AddressSpace>) {
+    let old = thread::set_address_space(Some(space.clone()));
+    let old_process = match current_pid() {
+        0 => None,
+        pid => without_interrupts(|| PROCESSES.get_mut().get(pid).space.replace(space)),
+    };
+    drop((old, old_process));
+}
+
+/// The file open as FD in the current process. Kernel threads have the console as
+/// their standard input, output and error.
+pub fn file(fd: u32) -> Result<File, Errno> {
+    match current_pid() {
+        0 if fd <= STDERR => Ok(File::Console),
+        0 => Err(Errno::EBADF),
+        pid => without_interrupts(|| PROCESSES.get_mut().get(pid).files.get(fd)),
+    }
+}
+
+/// Ends the current thread, making CODE the exit code of its process unless an earlier
+/// `exit` gave one. The other threads of the process run until they exit.
+pub fn exit(code: usize) -> ! {
+    let pid = current_pid();
+    if pid != 0 {
+        without_interrupts(|| {
+            PROCESSES.get_mut().get(pid).exit_code.get_or_insert(code);
+        });
+    }
+    thread::exit(code)
+}
+
+/// Takes the current thread out of its process, called by `thread::exit` with its exit
+/// code CODE. After the last thread, the process becomes a zombie and its children
+/// become children of init.
+pub(crate) fn exit_thread(code: usize) {
+    let pid = current_pid();
+    let tid = unsafe { (*thread::current()).tid() };
+    // The reference of the process may be the last one, it must go after the thread
+    // left the address space.
+    drop(thread::set_address_space(None));
+    let space = without_interrupts(|| {
+        let table = PROCESSES.get_mut();
+        let process = table.get(pid);
+        process.threads.retain(|&thread| thread != tid);
+        if !process.threads.is_empty() {
+            return None;
+        }
+        let code = *process.exit_code.get_or_insert(code);
+        process.state = ProcessState::Zombie(code);
+        process.files.clear();
+        let space = process.space.take();
+        let orphans = mem::take(&mut process.children);
+        for &orphan in &orphans {
+            table.get(orphan).parent = INIT_PID;
+        }
+        table.get(INIT_PID).children.extend(orphans);
+        space
+    });
+    drop(space);
+    CHILD_EXITED.wake_all();
+}
+
+/// Reaps a zombie child of PARENT, the child PID or any if `None`. Returns `None` if
+/// such children are all running. Interrupts must be disabled.
+fn reap(parent: u32, pid: Option<u32>) -> Option<Result<(u32, usize), Errno>> {
+    let table = PROCESSES.get_mut();
+    let children = &table.procs[&parent].children;
+    let matches = |child: &&u32| pid.is_none_or(|pid| pid == **child);
+    if !children.iter().any(|child| matches(&child)) {
+        return Some(Err(Errno::ECHILD));
+    }
+    let (child, code) =
+        children
+            .iter()
+            .filter(matches)
+            .find_map(|&child| match table.procs[&child].state {
+                ProcessState::Zombie(code) => Some((child, code)),
+                ProcessState::Running => None,
+            })?;
+    table.get(parent).children.retain(|&other| other != child);
+    table.procs.remove(&child);
+    Some(Ok((child, code)))
+}
+
+/// Waits for the child PID of the current process to exit, or any child if `None`, and
+/// reaps it. Returns its PID and exit code, `ECHILD` if there is no such child.
+pub fn waitpid(pid: Option<u32>) -> Result<(u32, usize), Errno> {
+    let parent = current_pid();
+    if parent == 0 {
+        return Err(Errno::ECHILD);
+    }
+    let mut result = None;
+    CHILD_EXITED.wait_event(|| {
+        result = reap(parent, pid);
+        result.is_some()
+    });
+    result.unwrap()
+}
+
+/// Waits for any child of the current process, see `waitpid`.
+pub fn wait() -> Result<(u32, usize), Errno> {
+    waitpid(None)
+}
+
+/// Thread of init: reaps the children of process 1 as they exit.
+fn init_thread(_: usize) -> usize {
+    join(INIT_PID);
+    loop {
+        match wait() {
+            Ok((pid, code)) => println!("process {} exited with code {}", pid, code),
+            Err(_) => {
+                CHILD_EXITED.wait_event(|| !PROCESSES.get_mut().get(INIT_PID).children.is_empty())
+            }
+        }
+    }
+}
+
+/// Creates init and starts its thread.
+pub fn init() {
+    without_interrupts(|| {
+        let table = PROCESSES.get_mut();
+        assert_eq!(table.alloc_pid(), Some(INIT_PID));
+        table
+            .procs
+            .insert(INIT_PID, Process::new(INIT_PID, 0, None));
+    });
+    thread::spawn(init_thread, 0);
+}
@@ -0,0 +1,294 @@
+#![allow(dead_code)]
+
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    arch::x86::{
+        self,
+        gdt::{KERNEL_CODE_SELECTOR, USER_CODE_SELECTOR, USER_DATA_SELECTOR},
+        intr::INTR_TABLE,
+        PrivilegeLevel, IA32_SYSENTER_CS, IA32_SYSENTER_EIP, IA32_SYSENTER_ESP,
+    },
+    irq::level::{self, Irql},
+    thread, time,
+};
+
+use super::{process, user_slice, user_slice_mut, vdso, Errno};
+
+/// System calls.
+///
+/// Ring 3 code raises `int 0x80` with the number of the call in EAX and its arguments in
+/// EBX, ECX, EDX, ESI and EDI, as on Linux. The call returns its result in EAX, or the
+/// negated `Errno` on failure, and preserves the other registers.
+///
+/// The gate is an interrupt gate, with a DPL of 3 so that ring 3 may raise it: the entry
+/// stub must reload the kernel segments before another interrupt nests. The call then
+/// runs with interrupts enabled, in the context of the calling thread, and may sleep.
+///
+/// On CPUs with SYSENTER, programs rather call the entry of the vDSO, see `vdso`, which
+/// takes the same registers. `sysenter_entry` builds the frame `int 0x80` would have, so
+/// both paths share the dispatch, and returns with SYSEXIT. SYSENTER loads the stack
+/// pointer from an MSR, which the scheduler points at the kernel stack of each thread
+/// along with the ESP0 of the TSS.
+
+pub const SYSCALL_VECTOR: u8 = 0x80;
+
+pub const SYS_EXIT: u32 = 0;
+pub const SYS_WRITE: u32 = 1;
+pub const SYS_GETPID: u32 = 2;
+pub const SYS_SLEEP: u32 = 3;
+pub const SYS_YIELD: u32 = 4;
+pub const SYS_UPTIME: u32 = 5;
+pub const SYS_WAITPID: u32 = 6;
+pub const SYS_GETPPID: u32 = 7;
+
+/// Registers of the caller, saved by `syscall_entry` above the frame of the CPU. ESP and
+/// SS are only there for a call from ring 3. For a call through SYSENTER, EBP is the
+/// user stack pointer as ESP, and EIP is the return point in the vDSO.
+#[derive(Debug)]
+#[repr(C)]
+pub struct SyscallFrame {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+    pub esp: u32,
+    pub ss: u32,
+}
+
+impl SyscallFrame {
+    /// Argument N of the call, from 0.
+    pub fn arg(&self, n: usize) -> u32 {
+        [self.ebx, self.ecx, self.edx, self.esi, self.edi][n]
+    }
+}
+
+pub type SyscallResult = Result<u32, Errno>;
+
+type SyscallFn = fn(&mut SyscallFrame) -> SyscallResult;
+
+/// Handler of each call, indexed by its number.
+static SYSCALL_TABLE: [SyscallFn; 8] = [
+    sys_exit,    // SYS_EXIT
+    sys_write,   // SYS_WRITE
+    sys_getpid,  // SYS_GETPID
+    sys_sleep,   // SYS_SLEEP
+    sys_yield,   // SYS_YIELD
+    sys_uptime,  // SYS_UPTIME
+    sys_waitpid, // SYS_WAITPID
+    sys_getppid, // SYS_GETPPID
+];
+
+global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    "    push ebp",
+    "    push edi",
+    "    push esi",
+    "    push edx",
+    "    push ecx",
+    "    push ebx",
+    "    push eax",
+    "    mov ebp, esp",
+    "    and esp, -16",
+    "    sub esp, 12",
+    "    push ebp",
+    "    cld",
+    "    call {dispatch}",
+    "    mov esp, ebp",
+    // Returns with interrupts enabled and without the kernel lock.
+    "    cli",
+    "    pop eax",
+    "    pop ebx",
+    "    pop ecx",
+    "    pop edx",
+    "    pop esi",
+    "    pop edi",
+    "    pop ebp",
+    "    iretd",
+    dispatch = sym syscall_dispatch,
+);
+
+/// Whether the CPUs take system calls through SYSENTER.
+static SYSENTER_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Where SYSEXIT returns to, read by `sysenter_entry`.
+#[no_mangle]
+static mut SYSENTER_RETURN: u32 = 0;
+
+global_asm!(
+    ".global sysenter_entry",
+    "sysenter_entry:",
+    // Interrupts are disabled, ESP is the top of the kernel stack and EBP the user one.
+    "    push {user_data}",
+    "    push ebp",
+    "    pushfd",
+    "    or dword ptr [esp], 0x200",
+    "    push {user_code}",
+    "    call intr_load_kernel_segments",
+    "    push dword ptr ds:[SYSENTER_RETURN]",
+    "    push ebp",
+    "    push edi",
+    "    push esi",
+    "    push edx",
+    "    push ecx",
+    "    push ebx",
+    "    push eax",
+    "    mov ebp, esp",
+    "    and esp, -16",
+    "    sub esp, 12",
+    "    push ebp",
+    "    cld",
+    "    call {dispatch}",
+    "    mov esp, ebp",
+    "    cli",
+    // SYSEXIT leaves the ring 0 segments in FS and GS, unlike iret.
+    "    xor eax, eax",
+    "    mov fs, ax",
+    "    mov gs, ax",
+    "    pop eax",
+    "    pop ebx",
+    // The stub restores ECX and EDX.
+    "    add esp, 8",
+    "    pop esi",
+    "    pop edi",
+    "    pop ebp",
+    "    mov edx, [esp]",
+    "    mov ecx, [esp + 12]",
+    // Interrupts are taken after SYSEXIT, in ring 3.
+    "    sti",
+    "    sysexit",
+    user_data = const USER_DATA_SELECTOR.0,
+    user_code = const USER_CODE_SELECTOR.0,
+    dispatch = sym syscall_dispatch,
+);
+
+extern "C" {
+    fn syscall_entry();
+    fn sysenter_entry();
+}
+
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) {
+    // The gate disabled the interrupts, the kernel lock was not taken.
+    x86::sti();
+    let number = frame.eax;
+    let result = match SYSCALL_TABLE.get(number as usize) {
+        Some(handler) => handler(frame),
+        None => Err(Errno::ENOSYS),
+    };
+    frame.eax = match result {
+        Ok(value) => value,
+        Err(errno) => errno.to_return(),
+    };
+    assert!(
+        x86::intr_enabled() && level::current_level() == Irql::PASSIVE,
+        "system call {} returning in atomic context",
+        number
+    );
+}
+
+/// Installs the gate of `int 0x80`, and SYSENTER on the bootstrap processor if the
+/// CPU has it.
+pub fn init() {
+    INTR_TABLE.get_mut()[SYSCALL_VECTOR as usize]
+        .set_handle_addr(syscall_entry as *const () as usize)
+        .set_privilege_level(PrivilegeLevel::Ring3);
+    if x86::has_sep() {
+        unsafe { SYSENTER_RETURN = vdso::sysenter_return() };
+        SYSENTER_ENABLED.store(true, Ordering::Relaxed);
+        init_cpu();
+    }
+}
+
+pub fn sysenter_enabled() -> bool {
+    SYSENTER_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Points SYSENTER at `sysenter_entry` on the running CPU. The APs are assumed to have
+/// SYSENTER as the bootstrap processor.
+pub(crate) fn init_cpu() {
+    if !sysenter_enabled() {
+        return;
+    }
+    x86::wrmsr(IA32_SYSENTER_CS, KERNEL_CODE_SELECTOR.0 as u64);
+    x86::wrmsr(IA32_SYSENTER_EIP, sysenter_entry as *const () as u64);
+    x86::wrmsr(IA32_SYSENTER_ESP, 0);
+}
+
+/// Makes SYSENTER land at TOP on the running CPU.
+pub(super) fn set_sysenter_stack(top: usize) {
+    if sysenter_enabled() {
+        x86::wrmsr(IA32_SYSENTER_ESP, top as u64);
+    }
+}
+
+/// `exit(code)`: ends the calling thread with exit code CODE, which becomes the one of
+/// its process.
+fn sys_exit(frame: &mut SyscallFrame) -> SyscallResult {
+    process::exit(frame.arg(0) as usize)
+}
+
+/// `write(fd, buf, len)`: writes LEN bytes at BUF to the file open as FD. Returns how
+/// many bytes were written.
+fn sys_write(frame: &mut SyscallFrame) -> SyscallResult {
+    let (fd, buf, len) = (frame.arg(0), frame.arg(1), frame.arg(2));
+    let file = process::file(fd)?;
+    let bytes = user_slice(buf as usize, len as usize)?;
+    file.write(bytes).map(|written| written as u32)
+}
+
+/// `getpid()`: identifier of the calling process.
+fn sys_getpid(_frame: &mut SyscallFrame) -> SyscallResult {
+    Ok(process::current_pid())
+}
+
+/// `getppid()`: identifier of the parent of the calling process.
+fn sys_getppid(_frame: &mut SyscallFrame) -> SyscallResult {
+    Ok(process::parent_pid())
+}
+
+/// `waitpid(pid, status)`: waits for the child PID to exit, or any child if PID is -1,
+/// and reaps it. Stores its exit code at STATUS unless null. Returns the PID of the
+/// child.
+fn sys_waitpid(frame: &mut SyscallFrame) -> SyscallResult {
+    let (pid, status) = (frame.arg(0) as i32, frame.arg(1) as usize);
+    let pid = match pid {
+        -1 => None,
+        pid if pid > 0 => Some(pid as u32),
+        _ => return Err(Errno::EINVAL),
+    };
+    if status != 0 {
+        user_slice_mut(status, 4)?;
+    }
+    let (child, code) = process::waitpid(pid)?;
+    if status != 0 {
+        user_slice_mut(status, 4)?.copy_from_slice(&(code as u32).to_le_bytes());
+    }
+    Ok(child)
+}
+
+/// `sleep(ms)`: sleeps at least MS milliseconds.
+fn sys_sleep(frame: &mut SyscallFrame) -> SyscallResult {
+    time::sleep(frame.arg(0) as u64);
+    Ok(0)
+}
+
+/// `yield()`: gives the CPU to the other ready threads.
+fn sys_yield(_frame: &mut SyscallFrame) -> SyscallResult {
+    thread::yield_now();
+    Ok(0)
+}
+
+/// `uptime()`: milliseconds since boot.
+fn sys_uptime(_frame: &mut SyscallFrame) -> SyscallResult {
+    Ok(time::uptime().as_millis() as u32)
+}
@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+
+use core::{
+    arch::global_asm,
+    ptr::{addr_of, copy_nonoverlapping},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    arch::x86::without_interrupts,
+    mm::{
+        self,
+        page::{self, PAGE_ALLOC, PAGE_SIZE, PG_USER},
+    },
+};
+
+use super::syscall::{self, SYSCALL_VECTOR};
+
+/// Page of kernel code shared with ring 3.
+///
+/// The page holds the system call entry of user programs at `VDSO_ADDR`: they call it
+/// with the registers of `int 0x80`, and it uses SYSENTER when the CPUs have it, or
+/// `int 0x80` otherwise. SYSENTER loses the user stack pointer and instruction pointer,
+/// so the stub saves ECX, EDX and EBP on the user stack and passes the stack pointer in
+/// EBP, and SYSEXIT always comes back to the stub.
+///
+/// The page is mapped read-only for ring 3 in the kernel page directory, address spaces
+/// made later map `frame()` at the same address.
+
+/// Where ring 3 finds the page, the last one below the kernel.
+pub const VDSO_ADDR: usize = 0xbfff_f000;
+
+/// Physical address of the page, 0 until `init`.
+static VDSO_FRAME: AtomicUsize = AtomicUsize::new(0);
+
+global_asm!(
+    ".global vdso_sysenter_start",
+    ".global vdso_sysenter_return",
+    ".global vdso_sysenter_end",
+    ".global vdso_int80_start",
+    ".global vdso_int80_end",
+    "vdso_sysenter_start:",
+    "    push ecx",
+    "    push edx",
+    "    push ebp",
+    "    mov ebp, esp",
+    "    sysenter",
+    "vdso_sysenter_return:",
+    "    pop ebp",
+    "    pop edx",
+    "    pop ecx",
+    "    ret",
+    "vdso_sysenter_end:",
+    "vdso_int80_start:",
+    "    int {vector}",
+    "    ret",
+    "vdso_int80_end:",
+    vector = const SYSCALL_VECTOR,
+);
+
+extern "C" {
+    static vdso_sysenter_start: u8;
+    static vdso_sysenter_return: u8;
+    static vdso_sysenter_end: u8;
+    static vdso_int80_start: u8;
+    static vdso_int80_end: u8;
+}
+
+/// Fills the page with the entry matching the CPUs and maps it.
+pub fn init() {
+    let (start, end) = if syscall::sysenter_enabled() {
+        (addr_of!(vdso_sysenter_start), addr_of!(vdso_sysenter_end))
+    } else {
+        (addr_of!(vdso_int80_start), addr_of!(vdso_int80_end))
+    };
+    without_interrupts(|| {
+        let page = PAGE_ALLOC
+            .get_mut()
+            .get_page(1)
+            .expect("out of memory for the vDSO");
+        unsafe {
+            page.write_bytes(0, PAGE_SIZE);
+            copy_nonoverlapping(start, page, end as usize - start as usize);
+        }
+        let frame = mm::vtop(page as usize);
+        page::map_page(page::kernel_page_dir(), VDSO_ADDR, frame, PG_USER);
+        VDSO_FRAME.store(frame, Ordering::Relaxed);
+    });
+}
+
+/// Physical address of the page.
+pub fn frame() -> usize {
+    VDSO_FRAME.load(Ordering::Relaxed)
+}
+
+/// Where SYSEXIT returns to in ring 3.
+pub(super) fn sysenter_return() -> u32 {
+    let offset = addr_of!(vdso_sysenter_return) as usize - addr_of!(vdso_sysenter_start) as usize;
+    (VDSO_ADDR + offset) as u32
+}
@@ -0,0 +1,146 @@
+use core::{
+    fmt,
+    ops::{Add, Div, Mul, Sub},
+};
+
+/// Number of fraction bits.
+const FRACTION_BITS: u32 = 14;
+
+const ONE: i32 = 1 << FRACTION_BITS;
+
+/// Signed 17.14 fixed-point number, for the few fractional values the kernel computes
+/// on a target built without floating point.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct Fixed(i32);
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+
+    pub const fn from_int(n: i32) -> Self {
+        Self(n * ONE)
+    }
+
+    /// N / D as a fixed-point number.
+    pub const fn from_ratio(n: i32, d: i32) -> Self {
+        Self((n as i64 * ONE as i64 / d as i64) as i32)
+    }
+
+    /// Rounds toward zero.
+    pub const fn trunc(self) -> i32 {
+        self.0 / ONE
+    }
+
+    /// Rounds to the nearest integer.
+    pub const fn round(self) -> i32 {
+        if self.0 >= 0 {
+            (self.0 + ONE / 2) / ONE
+        } else {
+            (self.0 - ONE / 2) / ONE
+        }
+    }
+
+    pub const fn add_int(self, n: i32) -> Self {
+        Self(self.0 + n * ONE)
+    }
+
+    pub const fn mul_int(self, n: i32) -> Self {
+        Self(self.0 * n)
+    }
+
+    pub const fn div_int(self, n: i32) -> Self {
+        Self(self.0 / n)
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 - rhs.0)
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, rhs: Fixed) -> Fixed {
+        Fixed(((self.0 as i64 * rhs.0 as i64) >> FRACTION_BITS) as i32)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+
+    fn div(self, rhs: Fixed) -> Fixed {
+        Fixed((((self.0 as i64) << FRACTION_BITS) / rhs.0 as i64) as i32)
+    }
+}
+
+impl fmt::Debug for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hundredths = self.mul_int(100).round();
+        let sign = if hundredths < 0 { "-" } else { "" };
+        let hundredths = hundredths.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, hundredths / 100, hundredths % 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::Fixed;
+
+    #[test]
+    fn rounds_half_away_from_zero() {
+        assert_eq!(Fixed::from_ratio(5, 2).round(), 3);
+        assert_eq!(Fixed::from_ratio(-5, 2).round(), -3);
+        assert_eq!(Fixed::from_ratio(7, 3).round(), 2);
+        assert_eq!(Fixed::from_ratio(-5, 2).trunc(), -2);
+    }
+
+    #[test]
+    fn arithmetic_matches_exact_fractions() {
+        let six = Fixed::from_int(6);
+        let three_halves = Fixed::from_ratio(3, 2);
+        assert_eq!(six * three_halves, Fixed::from_int(9));
+        assert_eq!(six / three_halves, Fixed::from_int(4));
+        assert_eq!((six + three_halves).mul_int(2), Fixed::from_int(15));
+        assert_eq!((three_halves - six).round(), -5);
+        assert_eq!(three_halves.add_int(1).div_int(5), Fixed::from_ratio(1, 2));
+    }
+
+    #[test]
+    fn products_and_quotients_do_not_overflow() {
+        // Both need more than the 32 bits of the operands in between.
+        let big = Fixed::from_int(100_000);
+        assert_eq!((big * Fixed::from_ratio(1, 4)).trunc(), 25_000);
+        assert_eq!((big / Fixed::from_int(1000)).trunc(), 100);
+    }
+
+    #[test]
+    fn load_average_tends_to_the_ready_count() {
+        let (decay, weight) = (Fixed::from_ratio(59, 60), Fixed::from_ratio(1, 60));
+        let mut load_avg = Fixed::ZERO;
+        for _ in 0..600 {
+            load_avg = decay * load_avg + weight.mul_int(3);
+        }
+        assert_eq!(load_avg.round(), 3);
+    }
+
+    #[test]
+    fn debug_shows_hundredths() {
+        assert_eq!(format!("{:?}", Fixed::from_ratio(3, 2)), "1.50");
+        assert_eq!(format!("{:?}", Fixed::from_ratio(-1, 4)), "-0.25");
+        assert_eq!(format!("{:?}", Fixed::ZERO), "0.00");
+    }
+}
@@ -2,6 +2,8 @@
 
 use core::ops::{Bound, Range, RangeBounds};
 
+pub mod fixed;
+pub mod ring;
 pub mod singleton;
 
 pub trait BitAccess {
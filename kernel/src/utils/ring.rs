@@ -0,0 +1,89 @@
+/// Fixed-capacity FIFO of N items.
+pub struct RingBuffer<T: Copy, const N: usize> {
+    items: [Option<T>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            items: [None; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Appends ITEM, returns `false` if the buffer is full.
+    pub fn push(&mut self, item: T) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.items[(self.head + self.len) % N] = Some(item);
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        item
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+
+    #[test]
+    fn pops_in_push_order_across_the_wrap() {
+        let mut ring = RingBuffer::<u32, 4>::new();
+        for i in 0..3 {
+            assert!(ring.push(i));
+        }
+        assert_eq!(ring.pop(), Some(0));
+        assert_eq!(ring.pop(), Some(1));
+        // The head is now at index 2, these wrap around the end of the array.
+        for i in 3..6 {
+            assert!(ring.push(i));
+        }
+        assert_eq!(ring.len(), 4);
+        for i in 2..6 {
+            assert_eq!(ring.pop(), Some(i));
+        }
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn push_to_full_buffer_keeps_contents() {
+        let mut ring = RingBuffer::<char, 2>::default();
+        assert!(ring.push('a') && ring.push('b'));
+        assert!(ring.is_full());
+        assert!(!ring.push('c'));
+        assert_eq!(ring.pop(), Some('a'));
+        assert_eq!(ring.pop(), Some('b'));
+        assert!(ring.is_empty());
+    }
+}